@@ -1,71 +1,804 @@
-use anyhow::Context as _;
-use clap::{builder::NonEmptyStringValueParser, Parser};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _};
+use clap::{
+    builder::NonEmptyStringValueParser, ArgAction, CommandFactory as _, FromArgMatches as _, Parser,
+};
 
 use xo65::Xo65;
 
-use ld65::{object::Object, script::LinkScript};
+use ld65::{
+    library::{select_modules, Library, LinkInput},
+    object::{parse_addr_size, Object, ADDR_SIZE_ABS},
+    script::{
+        builtin, LinkScript, LinkScriptMemoryType, LinkScriptParams, LinkScriptSegmentStart,
+        LinkScriptSegmentType, LinkScriptSymbolKind,
+    },
+    search::SearchPath,
+};
 
 #[derive(Debug, Parser)]
 struct Cli {
-    /// リンカスクリプトファイル名。
+    /// リンカスクリプトファイル名。省略時はターゲットのものを使う。
     #[arg(
-        required = true,
         short = 'C',
         long = "config",
         value_parser = NonEmptyStringValueParser::new()
     )]
-    path_script: String,
+    path_script: Option<String>,
+
+    /// ターゲットシステム名。-C がない場合、ターゲットのリンカスクリプトを使う。
+    #[arg(
+        short = 't',
+        long = "target",
+        value_parser = NonEmptyStringValueParser::new()
+    )]
+    target: Option<String>,
 
     /// メイン出力ファイル名。
     #[arg(
-        required = true,
         short = 'o',
         long = "output",
+        default_value = "a.out",
         value_parser = NonEmptyStringValueParser::new()
     )]
     path_out: String,
 
-    /// オブジェクトファイル名のリスト。
-    // required = true を付けることで 0 個のケースをエラーにできる
+    /// マップファイル名。
+    #[arg(
+        short = 'm',
+        long = "mapfile",
+        value_parser = NonEmptyStringValueParser::new()
+    )]
+    path_map: Option<String>,
+
+    /// JSON 形式のリンクレポートのファイル名。
+    #[arg(long = "report", value_parser = NonEmptyStringValueParser::new())]
+    path_report: Option<String>,
+
+    /// ラベルファイル名 (VICE 形式)。
+    #[arg(long = "Ln", value_parser = NonEmptyStringValueParser::new())]
+    path_labels: Option<String>,
+
+    /// デバッグ情報ファイル名 (未対応。指定しても警告を出して無視する)。
+    #[arg(long = "dbgfile", value_parser = NonEmptyStringValueParser::new())]
+    path_dbg: Option<String>,
+
+    /// リンカスクリプト内の "%S" の値。
+    #[arg(short = 'S', long = "start-addr", value_parser = parse_start_addr)]
+    start_addr: Option<u32>,
+
+    /// シンボル定義 (`name=value`)。リンカスクリプト内の weak なシンボルより優先される。
+    #[arg(short = 'D', long = "define", value_parser = parse_define)]
+    defines: Vec<(String, i64)>,
+
+    /// 強制インポートするシンボル (`name[:addrsize]`)。
+    #[arg(short = 'u', long = "force-import", value_parser = parse_force_import)]
+    force_imports: Vec<(String, u8)>,
+
+    /// ライブラリの検索ディレクトリ。
+    #[arg(short = 'L', long = "lib-path")]
+    lib_dirs: Vec<String>,
+
+    /// オブジェクトファイルの検索ディレクトリ。
+    #[arg(long = "obj-path")]
+    obj_dirs: Vec<String>,
+
+    /// リンカスクリプトの検索ディレクトリ。
+    #[arg(long = "cfg-path")]
+    cfg_dirs: Vec<String>,
+
+    /// ライブラリファイル名。
+    #[arg(long = "lib", value_parser = NonEmptyStringValueParser::new())]
+    libs: Vec<String>,
+
+    /// オブジェクトファイル名。
+    #[arg(long = "obj", value_parser = NonEmptyStringValueParser::new())]
+    objs: Vec<String>,
+
+    /// ライブラリのグループを開始する (`-(` も可)。
+    // 出現位置を全て記録するため、値を取らない Append とする (Count だと最後の位置しか残らない)。
+    #[arg(long = "start-group", action = ArgAction::Append, num_args = 0, default_missing_value = "true")]
+    start_group: Vec<bool>,
+
+    /// ライブラリのグループを終了する (`-)` も可)。
+    // 出現位置を全て記録するため、値を取らない Append とする (Count だと最後の位置しか残らない)。
+    #[arg(long = "end-group", action = ArgAction::Append, num_args = 0, default_missing_value = "true")]
+    end_group: Vec<bool>,
+
+    /// 詳細な情報を出力する。
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+
+    /// メモリ領域とセグメントの使用状況を標準出力に出力する。
+    #[arg(long = "stats")]
+    stats: bool,
+
+    /// 入力ファイル名のリスト。拡張子が .lib/.a のものはライブラリ、それ以外はオブジェクトファイルとみなす。
+    // --obj, --lib のみ指定するケースもあるので required にはしない
+    #[arg(value_parser = NonEmptyStringValueParser::new())]
+    inputs: Vec<String>,
+}
+
+/// `ld65 cfg-fmt`: リンカスクリプトを整形する。
+#[derive(Debug, Parser)]
+#[command(name = "ld65 cfg-fmt", bin_name = "ld65 cfg-fmt")]
+struct CfgFmtCli {
+    /// 整形結果でファイルを上書きする (指定しなければ標準出力へ出力する)。
+    #[arg(short = 'w', long = "write", conflicts_with = "check")]
+    write: bool,
+
+    /// 整形済みでないファイルがあれば失敗する (ファイルは変更しない)。
+    #[arg(long = "check")]
+    check: bool,
+
+    /// リンカスクリプトファイル名のリスト。
+    #[arg(required = true, value_parser = NonEmptyStringValueParser::new())]
+    paths: Vec<String>,
+}
+
+/// `ld65 check-config`: オブジェクトファイルなしでリンカスクリプトを検査する。
+///
+/// 評価と静的検査を行い、メモリ領域とセグメントの一覧を出力する。
+#[derive(Debug, Parser)]
+#[command(name = "ld65 check-config", bin_name = "ld65 check-config")]
+struct CheckConfigCli {
+    /// リンカスクリプトファイル名。省略時はターゲットのものを使う。
     #[arg(
-        required = true,
+        short = 'C',
+        long = "config",
         value_parser = NonEmptyStringValueParser::new()
     )]
-    paths_obj: Vec<String>,
+    path_script: Option<String>,
+
+    /// ターゲットシステム名。-C がない場合、ターゲットのリンカスクリプトを使う。
+    #[arg(
+        short = 't',
+        long = "target",
+        value_parser = NonEmptyStringValueParser::new()
+    )]
+    target: Option<String>,
+
+    /// メイン出力ファイル名 ("%O" の値)。
+    #[arg(
+        short = 'o',
+        long = "output",
+        default_value = "a.out",
+        value_parser = NonEmptyStringValueParser::new()
+    )]
+    path_out: String,
+
+    /// リンカスクリプト内の "%S" の値。
+    #[arg(short = 'S', long = "start-addr", value_parser = parse_start_addr)]
+    start_addr: Option<u32>,
+
+    /// シンボル定義 (`name=value`)。
+    #[arg(short = 'D', long = "define", value_parser = parse_define)]
+    defines: Vec<(String, i64)>,
+
+    /// リンカスクリプトの検索ディレクトリ。
+    #[arg(long = "cfg-path")]
+    cfg_dirs: Vec<String>,
+
+    /// 警告もエラーとみなす。
+    #[arg(long = "deny-warnings")]
+    deny_warnings: bool,
+
+    /// 一覧を出力しない。
+    #[arg(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// 詳細な情報を出力する。
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+}
+
+/// コマンドライン上の入力指定 (出現順に並べ直したもの)。
+#[derive(Debug)]
+enum InputArg {
+    Object(String),
+    Library(String),
+    StartGroup,
+    EndGroup,
+}
+
+/// 読み込んだ入力ファイル。
+#[derive(Debug)]
+enum InputFile {
+    Object { name: String, bytes: Vec<u8> },
+    Library { name: String, bytes: Vec<u8> },
+    StartGroup,
+    EndGroup,
 }
 
 fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    let args = expand_args(std::env::args())?;
 
-    let script = std::fs::read_to_string(&cli.path_script)
-        .with_context(|| format!("cannot read linker script '{}'", cli.path_script))?;
-    let script = LinkScript::load(&script, &cli.path_out)?;
+    // サブコマンド。オリジナルとの互換性のため、先頭の引数で判定する。
+    match args.get(1).map(String::as_str) {
+        Some("cfg-fmt") => return cfg_fmt(CfgFmtCli::parse_from(&args[1..])),
+        Some("check-config") => return check_config(CheckConfigCli::parse_from(&args[1..])),
+        _ => {}
+    }
 
-    let objs: Box<_> = cli
-        .paths_obj
+    let matches = Cli::command().get_matches_from(args);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let input_args = collect_input_args(&cli, &matches);
+    if !input_args
         .iter()
-        .map(|path| {
-            std::fs::read(path).with_context(|| format!("cannot read object file '{path}'"))
-        })
-        .collect::<Result<_, _>>()?;
-    let objs: Box<_> = objs
-        .iter()
-        .enumerate()
-        .map(|(i, obj)| {
-            let path = &cli.paths_obj[i];
-            Xo65::parse(obj)
-                .with_context(|| format!("cannot parse object file '{}'", path))
-                .map(|xo65| Object::new(path, xo65))
-        })
+        .any(|arg| matches!(arg, InputArg::Object(_) | InputArg::Library(_)))
+    {
+        bail!("no input files");
+    }
+
+    if let Some(path) = &cli.path_dbg {
+        eprintln!("warning: debug info file is not supported: '{path}' is not written");
+    }
+
+    // オブジェクトファイル (crt0.o など) はオリジナルと同様 lib ディレクトリに置かれる。
+    let obj_path = make_search_path(&cli.obj_dirs, "LD65_OBJ", "lib");
+    let lib_path = make_search_path(&cli.lib_dirs, "LD65_LIB", "lib");
+
+    let (script_name, script) = find_script(
+        cli.path_script.as_deref(),
+        cli.target.as_deref(),
+        &cli.cfg_dirs,
+        cli.verbose,
+    )?;
+    let mut params = LinkScriptParams::new();
+    for (name, value) in &cli.defines {
+        params.define_symbol(name, *value);
+    }
+    for (name, addr_size) in &cli.force_imports {
+        params.force_import(name, *addr_size);
+    }
+    if let Some(start_addr) = cli.start_addr {
+        params.set_start_addr(start_addr);
+    }
+    let script = LinkScript::load_source(&script_name, &script, &cli.path_out, &params)?;
+
+    // 入力ファイルを全て読み込む。
+    let files: Vec<_> = input_args
+        .into_iter()
+        .map(|arg| read_input(arg, &obj_path, &lib_path, cli.verbose))
         .collect::<Result<_, _>>()?;
 
-    let outputs = ld65::link::link(&script, &objs);
+    let inputs = parse_inputs(&files)?;
 
-    for output in outputs.iter() {
+    // リンカスクリプトで定義/インポートされたシンボルもライブラリの検索に影響する。
+    let defined = script.iter_symbols().filter_map(|sym| match sym.kind() {
+        LinkScriptSymbolKind::Export(_) | LinkScriptSymbolKind::Weak(_) => Some(sym.name()),
+        LinkScriptSymbolKind::Import => None,
+    });
+    let forced = script.iter_symbols().filter_map(|sym| match sym.kind() {
+        LinkScriptSymbolKind::Export(_) | LinkScriptSymbolKind::Weak(_) => None,
+        LinkScriptSymbolKind::Import => Some(sym.name()),
+    });
+    let objs = select_modules(inputs, defined, forced);
+    if cli.verbose {
+        for obj in &objs {
+            eprintln!("linking module '{}'", obj.name());
+        }
+    }
+
+    let result = ld65::link::link(&script, &objs);
+
+    for output in result.outputs() {
         let path = output.path();
         std::fs::write(path, output.body())
             .with_context(|| format!("cannot write output file '{path}'"))?;
     }
 
+    if let Some(path) = &cli.path_map {
+        std::fs::write(path, result.map())
+            .with_context(|| format!("cannot write map file '{path}'"))?;
+    }
+
+    if let Some(path) = &cli.path_report {
+        std::fs::write(path, result.report())
+            .with_context(|| format!("cannot write report file '{path}'"))?;
+    }
+
+    if let Some(path) = &cli.path_labels {
+        std::fs::write(path, result.labels())
+            .with_context(|| format!("cannot write label file '{path}'"))?;
+    }
+
+    if cli.stats {
+        print!("{}", result.stats());
+    }
+
+    Ok(())
+}
+
+/// ファイルを検索パスから探す。verbose モードでは検索結果を報告する。
+fn find_file(
+    search_path: &SearchPath,
+    name: &str,
+    what: &str,
+    verbose: bool,
+) -> anyhow::Result<PathBuf> {
+    let path = search_path.find(name);
+
+    if verbose {
+        match &path {
+            Some(path) => eprintln!("{what} '{name}': using '{}'", path.display()),
+            None => {
+                eprintln!("{what} '{name}': not found in the current directory and:");
+                for dir in search_path.iter_dirs() {
+                    eprintln!("    {}", dir.display());
+                }
+            }
+        }
+    }
+
+    path.with_context(|| format!("cannot find {what} '{name}'"))
+}
+
+/// リンカスクリプトを探して読み込み、(ファイル名, 内容) を返す。ファイル名はエラー報告用。
+///
+/// -C がなければターゲット名から、以下の順に探す:
+///
+/// 1. ユーザーが明示した検索ディレクトリ (--cfg-path, `LD65_CFG`)
+/// 2. 組み込みのリンカスクリプト
+/// 3. cc65 のインストール先 (`$CC65_HOME/cfg` など)
+///
+/// cc65 付属のリンカスクリプトはこのリンカで読めない構文を含むことがあるので、組み込みのものを優先する。
+fn find_script(
+    path_script: Option<&str>,
+    target: Option<&str>,
+    cfg_dirs: &[String],
+    verbose: bool,
+) -> anyhow::Result<(String, String)> {
+    match (path_script, target) {
+        (Some(path), _) => {
+            let cfg_path = make_search_path(cfg_dirs, "LD65_CFG", "cfg");
+            let path = find_file(&cfg_path, path, "linker script", verbose)?;
+            Ok((path.display().to_string(), read_script(&path)?))
+        }
+        (None, Some(target)) => {
+            let name = format!("{target}.cfg");
+            let user_cfg_path = make_user_search_path(cfg_dirs, "LD65_CFG");
+            if user_cfg_path.iter_dirs().len() > 0
+                && let Ok(path) = find_file(&user_cfg_path, &name, "linker script", verbose)
+            {
+                return Ok((path.display().to_string(), read_script(&path)?));
+            }
+            if let Some(script) = builtin(target) {
+                if verbose {
+                    eprintln!("using built-in linker script for target '{target}'");
+                }
+                return Ok((format!("<built-in {name}>"), script.to_owned()));
+            }
+            let cfg_path = make_search_path(cfg_dirs, "LD65_CFG", "cfg");
+            let path = find_file(&cfg_path, &name, "linker script", verbose)
+                .with_context(|| format!("unknown target '{target}' (no linker script found)"))?;
+            Ok((path.display().to_string(), read_script(&path)?))
+        }
+        (None, None) => bail!("no linker script specified (use -C or -t)"),
+    }
+}
+
+fn check_config(cli: CheckConfigCli) -> anyhow::Result<()> {
+    let (script_name, script) = find_script(
+        cli.path_script.as_deref(),
+        cli.target.as_deref(),
+        &cli.cfg_dirs,
+        cli.verbose,
+    )?;
+
+    let mut params = LinkScriptParams::new();
+    for (name, value) in &cli.defines {
+        params.define_symbol(name, *value);
+    }
+    if let Some(start_addr) = cli.start_addr {
+        params.set_start_addr(start_addr);
+    }
+    let script = LinkScript::load_source(&script_name, &script, &cli.path_out, &params)?;
+
+    if !cli.quiet {
+        print!("{}", format_config_table(&script));
+    }
+
+    let issues = script.check();
+    for issue in &issues {
+        eprintln!("{issue}");
+    }
+
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.is_error() || cli.deny_warnings)
+        .count();
+    if errors > 0 {
+        bail!("'{script_name}': {errors} problem(s) found");
+    }
+
+    Ok(())
+}
+
+/// メモリ領域とセグメントの一覧 (リンカスクリプトで指定された範囲) を表形式で返す。
+fn format_config_table(script: &LinkScript) -> String {
+    use std::fmt::Write as _;
+
+    let mut out = String::new();
+
+    let name_width = script
+        .iter_memorys()
+        .map(|mem| mem.name().len())
+        .chain(script.iter_segments().map(|seg| seg.name().len()))
+        .max()
+        .unwrap_or(0)
+        .max("Memory".len());
+
+    writeln!(out, "Memory areas:").unwrap();
+    writeln!(
+        out,
+        "{:<name_width$}  Start  End    Size   Type  Fill  File",
+        "Name"
+    )
+    .unwrap();
+    for mem in script.iter_memorys() {
+        let ty = match mem.ty() {
+            Some(LinkScriptMemoryType::Ro) => "ro",
+            Some(LinkScriptMemoryType::Rw) => "rw",
+            None => "-",
+        };
+        let fill = if mem.is_filled() { "yes" } else { "no" };
+        let file = mem
+            .outfile_idx()
+            .map_or("-", |outfile_i| script.outfile(outfile_i));
+        writeln!(
+            out,
+            "{:<name_width$}  ${:04X}  ${:04X}  ${:04X}  {ty:<4}  {fill:<4}  {file}",
+            mem.name(),
+            mem.range().min(),
+            mem.range().max(),
+            mem.len()
+        )
+        .unwrap();
+    }
+
+    // 自動配置されるセグメントは候補を全て示す。
+    let seg_mems: Vec<_> = script
+        .iter_segments()
+        .map(|seg| {
+            let names: Vec<_> = seg
+                .memory_candidates()
+                .map(|mem_i| script.memory(mem_i).name())
+                .collect();
+            names.join("|")
+        })
+        .collect();
+    let mem_width = seg_mems
+        .iter()
+        .map(String::len)
+        .max()
+        .unwrap_or(0)
+        .max(name_width);
+
+    writeln!(out).unwrap();
+    writeln!(out, "Segments:").unwrap();
+    writeln!(
+        out,
+        "{:<name_width$}  {:<mem_width$}  Type  Start",
+        "Name", "Memory"
+    )
+    .unwrap();
+    for (seg, mems) in script.iter_segments().zip(&seg_mems) {
+        let ty = match seg.ty() {
+            LinkScriptSegmentType::Ro => "ro",
+            LinkScriptSegmentType::Rw => "rw",
+            LinkScriptSegmentType::Bss => "bss",
+            LinkScriptSegmentType::Zp => "zp",
+        };
+        let start = match seg.start() {
+            LinkScriptSegmentStart::Unspecified => "-".to_owned(),
+            LinkScriptSegmentStart::Addr(addr) => format!("${addr:04X}"),
+            LinkScriptSegmentStart::Align(align) => format!("align ${align:X}"),
+        };
+        writeln!(
+            out,
+            "{:<name_width$}  {mems:<mem_width$}  {ty:<4}  {start}",
+            seg.name(),
+        )
+        .unwrap();
+    }
+
+    out
+}
+
+fn cfg_fmt(cli: CfgFmtCli) -> anyhow::Result<()> {
+    let mut unformatted = 0_usize;
+
+    for path in &cli.paths {
+        let script = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read linker script '{path}'"))?;
+        let formatted = ld65::script::format(path, &script)?;
+
+        if cli.check {
+            if formatted != script {
+                eprintln!("'{path}' is not formatted");
+                unformatted += 1;
+            }
+        } else if cli.write {
+            if formatted != script {
+                std::fs::write(path, &formatted)
+                    .with_context(|| format!("cannot write linker script '{path}'"))?;
+            }
+        } else {
+            print!("{formatted}");
+        }
+    }
+
+    if unformatted > 0 {
+        bail!("{unformatted} file(s) are not formatted");
+    }
+
     Ok(())
 }
+
+fn read_script(path: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("cannot read linker script '{}'", path.display()))
+}
+
+/// コマンドライン引数の前処理を行う。
+///
+/// * `@file` (レスポンスファイル) を展開する。ファイル内の各行が 1 つの引数となる。
+///   空行および '#' で始まる行は無視する。
+/// * clap で扱えない ld65 独自の短いオプション (`-Ln`, `-(`, `-)`) を長いオプションに置換する。
+fn expand_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Vec<String>> {
+    let mut res = Vec::<String>::new();
+
+    for (i, arg) in args.into_iter().enumerate() {
+        // 先頭はプログラム名。
+        if i == 0 {
+            res.push(arg);
+            continue;
+        }
+
+        if let Some(path) = arg.strip_prefix('@') {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("cannot read response file '{path}'"))?;
+            let lines = content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_owned);
+            res.extend(lines.map(rewrite_arg));
+        } else {
+            res.push(rewrite_arg(arg));
+        }
+    }
+
+    Ok(res)
+}
+
+fn rewrite_arg(arg: String) -> String {
+    match arg.as_str() {
+        "-Ln" => "--Ln".to_owned(),
+        "-(" => "--start-group".to_owned(),
+        "-)" => "--end-group".to_owned(),
+        _ => arg,
+    }
+}
+
+/// 入力ファイル関連の引数を出現順に並べる。
+fn collect_input_args(cli: &Cli, matches: &clap::ArgMatches) -> Vec<InputArg> {
+    fn indices(matches: &clap::ArgMatches, id: &str) -> Vec<usize> {
+        matches
+            .indices_of(id)
+            .map(Iterator::collect)
+            .unwrap_or_default()
+    }
+
+    let mut args = Vec::<(usize, InputArg)>::new();
+
+    for (idx, path) in indices(matches, "inputs").into_iter().zip(&cli.inputs) {
+        let arg = if is_library_name(path) {
+            InputArg::Library(path.clone())
+        } else {
+            InputArg::Object(path.clone())
+        };
+        args.push((idx, arg));
+    }
+    for (idx, path) in indices(matches, "libs").into_iter().zip(&cli.libs) {
+        args.push((idx, InputArg::Library(path.clone())));
+    }
+    for (idx, path) in indices(matches, "objs").into_iter().zip(&cli.objs) {
+        args.push((idx, InputArg::Object(path.clone())));
+    }
+    for idx in indices(matches, "start_group") {
+        args.push((idx, InputArg::StartGroup));
+    }
+    for idx in indices(matches, "end_group") {
+        args.push((idx, InputArg::EndGroup));
+    }
+
+    args.sort_by_key(|&(idx, _)| idx);
+
+    args.into_iter().map(|(_, arg)| arg).collect()
+}
+
+fn is_library_name(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("lib") || ext.eq_ignore_ascii_case("a"))
+}
+
+/// 組み込みの cc65 インストール先 (この下の cfg, lib を検索する)。
+#[cfg(unix)]
+const DEFAULT_CC65_HOMES: &[&str] = &["/usr/local/share/cc65", "/usr/share/cc65"];
+#[cfg(not(unix))]
+const DEFAULT_CC65_HOMES: &[&str] = &[];
+
+/// 検索パスを作る。オリジナルと同様、以下の順に検索する:
+///
+/// 1. コマンドラインで指定されたディレクトリ
+/// 2. 環境変数 `env_var` で指定されたディレクトリ
+/// 3. `$CC65_HOME/<subdir>`
+/// 4. 組み込みのディレクトリ (`DEFAULT_CC65_HOMES` の各要素の下の `subdir`)
+///
+/// (指定されたパスそのものは常に最初に試される。)
+fn make_search_path(dirs: &[String], env_var: &str, subdir: &str) -> SearchPath {
+    let mut path = make_user_search_path(dirs, env_var);
+    path.push_env_subdir("CC65_HOME", subdir);
+    for home in DEFAULT_CC65_HOMES {
+        path.push(Path::new(home).join(subdir));
+    }
+    path
+}
+
+/// ユーザーが明示した検索ディレクトリ (`make_search_path` の 1, 2) のみからなる検索パスを作る。
+fn make_user_search_path(dirs: &[String], env_var: &str) -> SearchPath {
+    let mut path = SearchPath::new();
+    for dir in dirs {
+        path.push(dir);
+    }
+    path.push_env(env_var);
+    path
+}
+
+/// 入力ファイルを検索パスから探して読み込む。
+fn read_input(
+    arg: InputArg,
+    obj_path: &SearchPath,
+    lib_path: &SearchPath,
+    verbose: bool,
+) -> anyhow::Result<InputFile> {
+    let read = |name: &str, search_path: &SearchPath| -> anyhow::Result<Vec<u8>> {
+        let path = find_file(search_path, name, "input file", verbose)?;
+        std::fs::read(&path).with_context(|| format!("cannot read input file '{}'", path.display()))
+    };
+
+    let file = match arg {
+        InputArg::Object(name) => {
+            let bytes = read(&name, obj_path)?;
+            // 拡張子に関わらず、中身がライブラリならライブラリとして扱う。
+            if Library::is_library(&bytes) {
+                InputFile::Library { name, bytes }
+            } else {
+                InputFile::Object { name, bytes }
+            }
+        }
+        InputArg::Library(name) => {
+            let bytes = read(&name, lib_path)?;
+            InputFile::Library { name, bytes }
+        }
+        InputArg::StartGroup => InputFile::StartGroup,
+        InputArg::EndGroup => InputFile::EndGroup,
+    };
+
+    Ok(file)
+}
+
+/// 読み込んだ入力ファイルをパースし、リンクへの入力の並びにする。
+fn parse_inputs(files: &[InputFile]) -> anyhow::Result<Vec<LinkInput<'_>>> {
+    let mut inputs = Vec::<LinkInput>::new();
+    // グループ内で読み込まれたライブラリ (グループ外なら None)。
+    let mut group = None::<Vec<Vec<Object>>>;
+
+    for file in files {
+        match file {
+            InputFile::Object { name, bytes } => {
+                let xo65 = Xo65::parse(bytes)
+                    .with_context(|| format!("cannot parse object file '{name}'"))?;
+                inputs.push(LinkInput::Object(Box::new(Object::new(name, xo65))));
+            }
+            InputFile::Library { name, bytes } => {
+                let modules = parse_library(name, bytes)?;
+                match &mut group {
+                    Some(group) => group.push(modules),
+                    None => inputs.push(LinkInput::Group(vec![modules])),
+                }
+            }
+            InputFile::StartGroup => {
+                if group.is_some() {
+                    bail!("library groups cannot be nested");
+                }
+                group = Some(Vec::new());
+            }
+            InputFile::EndGroup => {
+                let Some(libs) = group.take() else {
+                    bail!("--end-group without --start-group");
+                };
+                inputs.push(LinkInput::Group(libs));
+            }
+        }
+    }
+
+    if group.is_some() {
+        bail!("--start-group without --end-group");
+    }
+
+    Ok(inputs)
+}
+
+/// ライブラリ内の全モジュールをパースする。モジュール名は "lib(module)" の形式とする。
+fn parse_library<'data>(name: &str, bytes: &'data [u8]) -> anyhow::Result<Vec<Object<'data>>> {
+    let lib =
+        Library::parse(name, bytes).with_context(|| format!("cannot parse library '{name}'"))?;
+
+    lib.iter_modules()
+        .map(|module| {
+            let module_name = format!("{}({})", lib.name(), module.name());
+            Xo65::parse(module.bytes())
+                .with_context(|| format!("cannot parse object file '{module_name}'"))
+                .map(|xo65| Object::new(module_name, xo65))
+        })
+        .collect()
+}
+
+/// `-S` オプションの引数をパースする。
+fn parse_start_addr(s: &str) -> anyhow::Result<u32> {
+    let value = parse_number(s)?;
+    u32::try_from(value).with_context(|| format!("start address out of range: '{s}'"))
+}
+
+/// `-D` オプションの引数 (`name=value`) をパースする。
+fn parse_define(s: &str) -> anyhow::Result<(String, i64)> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected 'name=value': '{s}'"))?;
+    anyhow::ensure!(is_identifier(name), "invalid symbol name: '{name}'");
+    let value = parse_number(value)?;
+
+    Ok((name.to_owned(), value))
+}
+
+/// `-u` オプションの引数 (`name[:addrsize]`) をパースする。
+fn parse_force_import(s: &str) -> anyhow::Result<(String, u8)> {
+    let (name, addr_size) = match s.split_once(':') {
+        Some((name, addr_size)) => {
+            let addr_size = parse_addr_size(addr_size)
+                .ok_or_else(|| anyhow::anyhow!("invalid address size: '{addr_size}'"))?;
+            (name, addr_size)
+        }
+        None => (s, ADDR_SIZE_ABS),
+    };
+    anyhow::ensure!(is_identifier(name), "invalid symbol name: '{name}'");
+
+    Ok((name.to_owned(), addr_size))
+}
+
+/// 数値をパースする。'$' または "0x" が前置されていれば 16 進数とみなす。
+fn parse_number(s: &str) -> anyhow::Result<i64> {
+    let value = if let Some(hex) = s.strip_prefix('$') {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+
+    value.with_context(|| format!("invalid number: '{s}'"))
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
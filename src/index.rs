@@ -50,3 +50,6 @@ define_index!(ObjImportIdx);
 
 // オブジェクトファイル内ローカルな文字列インデックス。
 define_index!(ObjStrIdx);
+
+// 全オブジェクトファイルおよびリンカスクリプトを通じたエクスポートシンボルのインデックス。
+define_index!(ExportIdx);
@@ -1,5 +1,10 @@
 pub mod index;
+pub mod library;
 pub mod link;
 pub mod object;
 pub mod range;
 pub mod script;
+pub mod search;
+
+#[cfg(test)]
+mod testutil;
@@ -0,0 +1,255 @@
+//! ライブラリファイル (ar65 形式) 関連。
+
+use anyhow::{bail, ensure, Context as _};
+
+use crate::object::Object;
+
+/// ライブラリファイル。
+///
+/// 各モジュールのオブジェクトファイルとしての内容はパースせず、バイト列のまま保持する。
+#[derive(Debug)]
+pub struct Library<'data> {
+    name: String,
+    modules: Box<[LibraryModule<'data>]>,
+}
+
+impl<'data> Library<'data> {
+    const MAGIC: u32 = 0x7A55616E;
+    const VERSION: u16 = 0x000D;
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn iter_modules(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &LibraryModule<'data>> + std::iter::FusedIterator + Clone
+    {
+        self.modules.iter()
+    }
+
+    /// バイト列がライブラリファイルのマジックナンバーで始まるかどうかを返す。
+    pub fn is_library(bytes: &[u8]) -> bool {
+        bytes.get(..4) == Some(&Self::MAGIC.to_le_bytes())
+    }
+
+    /// ar65 形式のライブラリファイルをパースする。
+    pub fn parse<S: Into<String>>(name: S, bytes: &'data [u8]) -> anyhow::Result<Self> {
+        let name = name.into();
+        let mut reader = Reader::new(bytes);
+
+        let magic = reader.read_u32().context("library header not found")?;
+        ensure!(magic == Self::MAGIC, "library magic not found");
+        let version = reader.read_u16().context("library header not found")?;
+        ensure!(
+            version == Self::VERSION,
+            "unsupported library version: {version:#X}"
+        );
+        let _flags = reader.read_u16().context("library header not found")?;
+        let index_off = reader.read_u32().context("library header not found")? as usize;
+
+        let mut reader = Reader::new(bytes.get(index_off..).context("library index not found")?);
+        let count = reader.read_var().context("library index parse error")? as usize;
+
+        let mut modules = Vec::<LibraryModule>::with_capacity(count);
+
+        for i in 0..count {
+            let module = (|| {
+                let name = reader.read_str()?;
+                let _flags = reader.read_u16()?;
+                let _mtime = reader.read_u32()?;
+                let start = reader.read_u32()? as usize;
+                let len = reader.read_u32()? as usize;
+                let bytes = bytes
+                    .get(start..)
+                    .and_then(|bytes| bytes.get(..len))
+                    .context("module body is out of range")?;
+                anyhow::Ok(LibraryModule { name, bytes })
+            })()
+            .with_context(|| format!("library module {i} parse error"))?;
+            modules.push(module);
+        }
+
+        Ok(Self {
+            name,
+            modules: modules.into(),
+        })
+    }
+}
+
+/// ライブラリファイル内のモジュール (オブジェクトファイル)。
+#[derive(Debug)]
+pub struct LibraryModule<'data> {
+    name: String,
+    bytes: &'data [u8],
+}
+
+impl<'data> LibraryModule<'data> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// オブジェクトファイルとしての内容を返す。
+    pub fn bytes(&self) -> &'data [u8] {
+        self.bytes
+    }
+}
+
+/// リンクへの入力 (コマンドライン上の順に並ぶ)。
+#[derive(Debug)]
+pub enum LinkInput<'data> {
+    /// オブジェクトファイル。常にリンクされる。
+    Object(Box<Object<'data>>),
+    /// ライブラリのグループ (単独のライブラリは要素数 1 のグループとみなす)。
+    ///
+    /// 各要素はライブラリ内の全モジュールをパースしたもの。
+    /// グループ内のライブラリは、未解決のシンボルがなくなるまで繰り返し検索される。
+    Group(Vec<Vec<Object<'data>>>),
+}
+
+/// 入力の並びから、実際にリンクするオブジェクトファイルを選択する。
+///
+/// オブジェクトファイルは全て選択される。ライブラリ内のモジュールは、
+/// その時点で未解決のシンボルをエクスポートしている場合のみ選択される。
+/// `defined` は予め定義済みのシンボル (リンカスクリプトで定義されたものなど)、
+/// `forced` は強制インポートされたシンボル。
+///
+/// 戻り値の順序は入力の順序に従う (ライブラリから選択されたモジュールはライブラリの位置に入る)。
+pub fn select_modules<'data, 'a>(
+    inputs: Vec<LinkInput<'data>>,
+    defined: impl IntoIterator<Item = &'a str>,
+    forced: impl IntoIterator<Item = &'a str>,
+) -> Vec<Object<'data>> {
+    let mut state = SymbolState::default();
+    for name in defined {
+        state.export(name);
+    }
+    for name in forced {
+        state.import(name);
+    }
+
+    let mut objs = Vec::<Object>::new();
+
+    for input in inputs {
+        match input {
+            LinkInput::Object(obj) => {
+                state.add_object(&obj);
+                objs.push(*obj);
+            }
+            LinkInput::Group(libs) => {
+                let mut libs: Vec<Vec<Option<Object>>> = libs
+                    .into_iter()
+                    .map(|modules| modules.into_iter().map(Some).collect())
+                    .collect();
+
+                // モジュールを追加すると新たな未解決シンボルが生じうるので、変化がなくなるまで繰り返す。
+                loop {
+                    let mut changed = false;
+
+                    for module in libs.iter_mut().flatten() {
+                        let wanted = module.as_ref().is_some_and(|obj| {
+                            obj.iter_export_names()
+                                .any(|name| state.is_unresolved(name))
+                        });
+                        if wanted {
+                            let obj = module.take().unwrap();
+                            state.add_object(&obj);
+                            objs.push(obj);
+                            changed = true;
+                        }
+                    }
+
+                    if !changed {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    objs
+}
+
+/// モジュール選択時のシンボルの状態。
+#[derive(Debug, Default)]
+struct SymbolState {
+    exported: std::collections::HashSet<String>,
+    unresolved: std::collections::HashSet<String>,
+}
+
+impl SymbolState {
+    fn add_object(&mut self, obj: &Object) {
+        for name in obj.iter_export_names() {
+            self.export(name);
+        }
+        for name in obj.iter_import_names() {
+            self.import(name);
+        }
+    }
+
+    fn export(&mut self, name: &str) {
+        self.unresolved.remove(name);
+        self.exported.insert(name.to_owned());
+    }
+
+    fn import(&mut self, name: &str) {
+        if !self.exported.contains(name) {
+            self.unresolved.insert(name.to_owned());
+        }
+    }
+
+    fn is_unresolved(&self, name: &str) -> bool {
+        self.unresolved.contains(name)
+    }
+}
+
+/// ライブラリファイル内の値を読み取るためのリーダー (値は全てリトルエンディアン)。
+#[derive(Debug)]
+struct Reader<'data> {
+    bytes: &'data [u8],
+}
+
+impl<'data> Reader<'data> {
+    fn new(bytes: &'data [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> anyhow::Result<&'data [u8]> {
+        ensure!(self.bytes.len() >= len, "unexpected end of data");
+        let (res, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(res)
+    }
+
+    fn read_u16(&mut self) -> anyhow::Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// 可変長整数 (LEB128) を読み取る。
+    fn read_var(&mut self) -> anyhow::Result<u32> {
+        let mut value = 0_u32;
+
+        for shift in (0..32).step_by(7) {
+            let b = self.read_bytes(1)?[0];
+            value |= u32::from(b & 0x7F) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        bail!("variable-length integer is too long")
+    }
+
+    fn read_str(&mut self) -> anyhow::Result<String> {
+        let len = self.read_var()? as usize;
+        let bytes = self.read_bytes(len)?;
+        let s = std::str::from_utf8(bytes).context("string is not utf-8")?;
+        Ok(s.to_owned())
+    }
+}
@@ -0,0 +1,132 @@
+//! セグメントのサイズの上限 (maxsize 属性) と出力ファイルの期待されるサイズ (FILES ブロックの size 属性) の検査。
+
+use crate::script::LinkScript;
+
+use super::graph::LinkGraph;
+use super::layout::LinkLayout;
+
+/// リンカスクリプトで指定された全てのサイズの上限/期待値と、実際のサイズを列挙する。
+pub fn collect_size_budgets(
+    script: &LinkScript,
+    graph: &LinkGraph,
+    layout: &LinkLayout,
+) -> Box<[SizeBudget]> {
+    let mut budgets = Vec::<SizeBudget>::new();
+
+    for (seg_i, seg) in script.enumerate_segments() {
+        let Some(max_size) = seg.max_size() else {
+            continue;
+        };
+        // 分割配置されたセグメントは全部分の合計、複製されたセグメントは 1 つ分とする。
+        let used = if seg.is_replicated() {
+            layout.segment(seg_i).len()
+        } else {
+            graph
+                .segs()
+                .filter(|&part_i| graph.seg_to_script_seg(part_i) == seg_i)
+                .map(|part_i| layout.segment(part_i).len())
+                .sum()
+        };
+        budgets.push(SizeBudget {
+            kind: SizeBudgetKind::Segment,
+            name: seg.name().to_owned(),
+            used,
+            budget: max_size,
+        });
+    }
+
+    for file_i in graph.files() {
+        let Some(size) = script.outfile_size(file_i) else {
+            continue;
+        };
+        budgets.push(SizeBudget {
+            kind: SizeBudgetKind::File,
+            name: graph.file_name(file_i).to_owned(),
+            used: layout.file(file_i).len(),
+            budget: size,
+        });
+    }
+
+    budgets.into()
+}
+
+/// サイズの上限/期待値を満たさないものがあれば、それらを全て挙げて panic する。
+pub fn check_size_budgets(budgets: &[SizeBudget]) {
+    let violations: Vec<_> = budgets
+        .iter()
+        .filter(|budget| !budget.is_satisfied())
+        .map(|budget| format!("  {budget}"))
+        .collect();
+
+    assert!(
+        violations.is_empty(),
+        "size budget check failed:\n{}",
+        violations.join("\n")
+    );
+}
+
+/// サイズの上限/期待値の種類。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SizeBudgetKind {
+    /// セグメントのサイズの上限 (maxsize 属性)。実際のサイズはこれ以下でなければならない。
+    Segment,
+    /// 出力ファイルの期待されるサイズ (FILES ブロックの size 属性)。実際のサイズはこれと一致しなければならない。
+    File,
+}
+
+/// サイズの上限/期待値と、実際のサイズ。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SizeBudget {
+    kind: SizeBudgetKind,
+    name: String,
+    used: usize,
+    budget: usize,
+}
+
+impl SizeBudget {
+    pub fn kind(&self) -> SizeBudgetKind {
+        self.kind
+    }
+
+    /// セグメント名または出力ファイル名を返す。
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 実際のサイズを返す。
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// 上限 (セグメント) または期待されるサイズ (出力ファイル) を返す。
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// 上限/期待値を満たすかどうかを返す。
+    pub fn is_satisfied(&self) -> bool {
+        match self.kind {
+            SizeBudgetKind::Segment => self.used <= self.budget,
+            SizeBudgetKind::File => self.used == self.budget,
+        }
+    }
+}
+
+impl std::fmt::Display for SizeBudget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self { used, budget, .. } = *self;
+        let diff = used as i64 - budget as i64;
+        match self.kind {
+            SizeBudgetKind::Segment => write!(
+                f,
+                "segment '{}': ${used:04X} ({used}) bytes used of ${budget:04X} ({budget}) bytes ({diff:+})",
+                self.name
+            ),
+            SizeBudgetKind::File => write!(
+                f,
+                "file '{}': ${used:04X} ({used}) bytes, expected ${budget:04X} ({budget}) bytes ({diff:+})",
+                self.name
+            ),
+        }
+    }
+}
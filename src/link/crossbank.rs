@@ -0,0 +1,267 @@
+//! 切り替え可能なバンク間の参照の検出。
+//!
+//! あるバンクのコードが別の切り替え可能なバンク内のアドレスを直接参照している場合、
+//! 実行時には参照先のバンクがマップされていないことが多い (固定バンク経由で切り替えるべき)。
+
+use xo65::expr::{Expr, ExprBinary, ExprUnary, OpUnary};
+use xo65::section::SectionFragmentBody;
+
+use crate::index::{MemIdx, ObjImportIdx};
+use crate::object::Object;
+use crate::script::LinkScript;
+
+use super::graph::LinkGraph;
+use super::layout::LinkLayout;
+use super::symbol::{eval_expr, SymbolTable};
+
+/// 切り替え可能なバンクから、別の切り替え可能なバンクへの参照を列挙する。
+///
+/// 対象は 16 bit の絶対アドレスを直接オペランドとする参照 (`jsr target`, `lda target` など) のみ。
+/// `lda #<target` のようなバイト抽出や `.faraddr target` のような 24 bit のアドレスは、固定バンク経由で
+/// バンクを切り替えるための準備 (トランポリンへのアドレスの受け渡しなど) として正当に使われるので報告しない。
+///
+/// いずれかのメモリ領域に fixed 属性がある場合のみ検査する (ない場合は空を返す)。
+/// bank 属性があり、fixed 属性がないメモリ領域を切り替え可能なバンクとみなす。
+/// 異なるメモリ領域でも bank 属性の値が同じなら、同じバンクとみなす。
+pub fn find_cross_bank_refs(
+    script: &LinkScript,
+    objs: &[Object],
+    graph: &LinkGraph,
+    layout: &LinkLayout,
+    sym_table: &SymbolTable,
+) -> Box<[CrossBankRef]> {
+    let mut refs = Vec::<CrossBankRef>::new();
+
+    if !script.iter_memorys().any(|mem| mem.is_fixed()) {
+        return refs.into();
+    }
+
+    let switchable_bank = |mem_i: MemIdx| {
+        let mem = script.memory(mem_i);
+        mem.is_switchable().then(|| mem.bank().unwrap())
+    };
+
+    for sect_i in graph.sects() {
+        let from_mem_i = graph.seg_to_mem(graph.sect_to_seg(sect_i));
+        let Some(from_bank) = switchable_bank(from_mem_i) else {
+            continue;
+        };
+
+        let (obj_i, obj_sect_i) = graph.sect_to_obj_sect(sect_i);
+        let obj = &objs[obj_i.get()];
+        let sect_start = layout.section(sect_i).start();
+
+        let mut off = 0;
+        for frag in obj.section(obj_sect_i).fragments() {
+            let (expr, len) = match frag.body() {
+                SectionFragmentBody::Literal(lit) => {
+                    off += lit.len();
+                    continue;
+                }
+                SectionFragmentBody::Fill(len) => {
+                    off += *len as usize;
+                    continue;
+                }
+                SectionFragmentBody::ExprU8(expr) | SectionFragmentBody::ExprI8(expr) => (expr, 1),
+                SectionFragmentBody::ExprU16(expr) | SectionFragmentBody::ExprI16(expr) => {
+                    (expr, 2)
+                }
+                SectionFragmentBody::ExprU24(expr) | SectionFragmentBody::ExprI24(expr) => {
+                    (expr, 3)
+                }
+                SectionFragmentBody::ExprU32(expr) | SectionFragmentBody::ExprI32(expr) => {
+                    (expr, 4)
+                }
+            };
+
+            if !matches!(frag.body(), SectionFragmentBody::ExprU16(_)) || is_byte_extract(expr) {
+                off += len;
+                continue;
+            }
+
+            let mut symbol = |imp_i| sym_table.get(obj_i, imp_i).expr_value();
+            let value = eval_expr(graph, layout, obj_i, expr, &mut symbol);
+            if let Some(to_sect_i) = value.section_idx() {
+                let to_mem_i = graph.seg_to_mem(graph.sect_to_seg(to_sect_i));
+                if let Some(to_bank) = switchable_bank(to_mem_i)
+                    && to_bank != from_bank
+                {
+                    refs.push(CrossBankRef {
+                        object: obj.name().to_owned(),
+                        location: obj.query_source_location(frag.lines()),
+                        addr: sect_start + off,
+                        symbol: first_import(expr).map(|imp_i| {
+                            obj.query_import_name(ObjImportIdx::new(imp_i)).to_owned()
+                        }),
+                        value: value.value(),
+                        from_mem: graph.mem_name(from_mem_i).to_owned(),
+                        from_bank,
+                        to_mem: graph.mem_name(to_mem_i).to_owned(),
+                        to_bank,
+                    });
+                }
+            }
+
+            off += len;
+        }
+    }
+
+    refs.into()
+}
+
+/// 式がバイト抽出 (`<expr`, `>expr`, `^expr` など) かどうかを返す。
+fn is_byte_extract(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Unary(unary) if matches!(
+            unary.op,
+            OpUnary::Byte0 | OpUnary::Byte1 | OpUnary::Byte2 | OpUnary::Byte3
+        )
+    )
+}
+
+/// 式中で最初に現れるインポートシンボルを返す。
+fn first_import(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Symbol { import_idx } => Some(*import_idx as usize),
+        Expr::Unary(unary) => {
+            let ExprUnary { expr, .. } = unary.as_ref();
+            first_import(expr)
+        }
+        Expr::Binary(binary) => {
+            let ExprBinary { lhs, rhs, .. } = binary.as_ref();
+            first_import(lhs).or_else(|| first_import(rhs))
+        }
+        _ => None,
+    }
+}
+
+/// 切り替え可能なバンク間の参照。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CrossBankRef {
+    object: String,
+    location: Option<String>,
+    addr: usize,
+    symbol: Option<String>,
+    value: i64,
+    from_mem: String,
+    from_bank: u32,
+    to_mem: String,
+    to_bank: u32,
+}
+
+impl CrossBankRef {
+    /// 参照元のオブジェクトファイル名を返す。
+    pub fn object_name(&self) -> &str {
+        &self.object
+    }
+
+    /// 参照元のソース上の位置 ("ファイル名:行") を返す。行情報がない場合は `None`。
+    pub fn source_location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    /// 参照元のアドレスを返す。
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    /// 参照しているシンボル名を返す。シンボルを介さない (セクション相対の) 参照の場合は `None`。
+    pub fn symbol_name(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    /// 参照先の値 (アドレス) を返す。
+    pub fn value(&self) -> i64 {
+        self.value
+    }
+
+    /// 参照元のメモリ領域名とバンク番号を返す。
+    pub fn from(&self) -> (&str, u32) {
+        (&self.from_mem, self.from_bank)
+    }
+
+    /// 参照先のメモリ領域名とバンク番号を返す。
+    pub fn to(&self) -> (&str, u32) {
+        (&self.to_mem, self.to_bank)
+    }
+}
+
+impl std::fmt::Display for CrossBankRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{location} ('{}')", self.object)?,
+            None => write!(f, "'{}' at ${:04X}", self.object, self.addr)?,
+        }
+        let target = match &self.symbol {
+            Some(symbol) => format!("'{symbol}' (${:04X})", self.value),
+            None => format!("${:04X}", self.value),
+        };
+        write!(
+            f,
+            ": reference from memory '{}' (bank {}) to {target} in memory '{}' (bank {}) bypasses the fixed bank",
+            self.from_mem, self.from_bank, self.to_mem, self.to_bank
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::link::link;
+    use crate::object::ADDR_SIZE_ABS;
+    use crate::script::LinkScript;
+    use crate::testutil::{parse_object, TestExpr, TestObject};
+
+    #[test]
+    fn only_direct_absolute_operands_are_reported() {
+        let script = LinkScript::load(
+            r#"
+MEMORY {
+    PRG0: start = $8000, size = $10, bank = 0;
+    PRG1: start = $8000, size = $10, bank = 1;
+    FIX:  start = $C000, size = $10, bank = 7, fixed = yes;
+}
+SEGMENTS {
+    BANK0: load = PRG0;
+    BANK1: load = PRG1;
+}
+"#,
+            "out.bin",
+        )
+        .unwrap();
+
+        let mut target = TestObject::new("target");
+        let sect_i = target.section("BANK1");
+        target.literal(sect_i, &[0x60]);
+        target.export("target", TestExpr::Section(sect_i, 0), ADDR_SIZE_ABS);
+        let target = target.encode();
+
+        let mut caller = TestObject::new("caller");
+        let sect_i = caller.section("BANK0");
+        let imp_i = caller.import("target", ADDR_SIZE_ABS);
+        let symbol = || Box::new(TestExpr::Symbol(imp_i));
+        // トランポリンの準備 (lda #<target / ldx #>target / .faraddr target) は報告しない。
+        caller.literal(sect_i, &[0xA9]);
+        caller.expr(sect_i, 1, TestExpr::Byte0(symbol()));
+        caller.literal(sect_i, &[0xA2]);
+        caller.expr(sect_i, 1, TestExpr::Byte1(symbol()));
+        caller.expr(sect_i, 3, TestExpr::Symbol(imp_i));
+        // jsr target は報告する。
+        caller.literal(sect_i, &[0x20]);
+        caller.expr(sect_i, 2, TestExpr::Symbol(imp_i));
+        let caller = caller.encode();
+
+        let objs = [
+            parse_object("caller", &caller),
+            parse_object("target", &target),
+        ];
+        let result = link(&script, &objs);
+
+        let refs: Vec<_> = result.cross_bank_refs().collect();
+        assert_eq!(refs.len(), 1, "{refs:?}");
+        assert_eq!(refs[0].addr(), 0x8008);
+        assert_eq!(refs[0].symbol_name(), Some("target"));
+        assert_eq!(refs[0].value(), 0x8000);
+        assert_eq!(refs[0].to(), ("PRG1", 1));
+    }
+}
@@ -1,14 +1,11 @@
-use xo65::{
-    expr::{Expr, ExprBinary, ExprUnary},
-    section::SectionFragmentBody,
-};
+use xo65::{expr::Expr, section::SectionFragmentBody};
 
-use crate::index::{MemIdx, ObjIdx, ObjImportIdx, ObjSectIdx, OutFileIdx, SegIdx};
+use crate::index::{MemIdx, ObjIdx, ObjSectIdx, OutFileIdx, SegIdx};
 use crate::object::Object;
 
 use super::graph::LinkGraph;
 use super::layout::LinkLayout;
-use super::symbol::SymbolTable;
+use super::symbol::{eval_expr, ExprValue, SymbolTable};
 
 pub fn emit_file(
     objs: &[Object],
@@ -104,12 +101,22 @@ impl<'objs, 'data, 'graph, 'layout, 'sym_table> Emitter<'objs, 'data, 'graph, 'l
 
         macro_rules! emit_expr {
             ($ty:ty, $expr:expr) => {{
-                let value = self.eval_expr(obj_i, $expr);
+                let value = self.eval_expr(obj_i, $expr).value();
                 let value: $ty = value.try_into().expect("expr value overflow");
                 value.emit_at(buf, &mut off);
             }};
         }
 
+        // 24 bit の値はバンクの整合性も検査する。
+        macro_rules! emit_expr_far {
+            ($ty:ty, $expr:expr) => {{
+                let value = self.eval_expr(obj_i, $expr);
+                self.check_far_bank(obj, obj_sect_i, off, value);
+                let value: $ty = value.value().try_into().expect("expr value overflow");
+                value.emit_at(buf, &mut off);
+            }};
+        }
+
         for frag in obj.section(obj_sect_i).fragments() {
             match frag.body() {
                 SectionFragmentBody::Literal(lit) => lit.emit_at(buf, &mut off),
@@ -118,45 +125,37 @@ impl<'objs, 'data, 'graph, 'layout, 'sym_table> Emitter<'objs, 'data, 'graph, 'l
                 }
                 SectionFragmentBody::ExprU8(expr) => emit_expr!(u8, expr),
                 SectionFragmentBody::ExprU16(expr) => emit_expr!(u16, expr),
-                SectionFragmentBody::ExprU24(expr) => emit_expr!(U24, expr),
+                SectionFragmentBody::ExprU24(expr) => emit_expr_far!(U24, expr),
                 SectionFragmentBody::ExprU32(expr) => emit_expr!(u32, expr),
                 SectionFragmentBody::ExprI8(expr) => emit_expr!(i8, expr),
                 SectionFragmentBody::ExprI16(expr) => emit_expr!(i16, expr),
-                SectionFragmentBody::ExprI24(expr) => emit_expr!(I24, expr),
+                SectionFragmentBody::ExprI24(expr) => emit_expr_far!(I24, expr),
                 SectionFragmentBody::ExprI32(expr) => emit_expr!(i32, expr),
             }
         }
     }
 
-    fn eval_expr(&self, obj_i: ObjIdx, expr: &Expr) -> i64 {
-        match expr {
-            Expr::Null => panic!("expr is null"),
-            Expr::Literal { value } => *value,
-            Expr::Symbol { import_idx } => {
-                let obj_imp_i = ObjImportIdx::new(*import_idx as usize);
-                self.sym_table.get(obj_i, obj_imp_i).value()
-            }
-            Expr::Section { section_idx } => {
-                let obj_sect_i = ObjSectIdx::new(*section_idx as usize);
-                let sect_i = self
-                    .graph
-                    .obj_sect_to_sect(obj_i, obj_sect_i)
-                    .unwrap_or_else(|| {
-                        panic!("unknown section: obj_i={obj_i}, obj_sect_i={obj_sect_i}")
-                    });
-                self.layout.section(sect_i).start() as i64
-            }
-            Expr::Unary(unary) => {
-                let ExprUnary { op, expr } = unary.as_ref();
-                let expr_value = self.eval_expr(obj_i, expr);
-                op.apply(expr_value)
-            }
-            Expr::Binary(binary) => {
-                let ExprBinary { op, lhs, rhs } = binary.as_ref();
-                let lhs_value = self.eval_expr(obj_i, lhs);
-                let rhs_value = self.eval_expr(obj_i, rhs);
-                op.apply(lhs_value, rhs_value)
-            }
+    fn eval_expr(&self, obj_i: ObjIdx, expr: &Expr) -> ExprValue {
+        let mut symbol = |imp_i| self.sym_table.get(obj_i, imp_i).expr_value();
+
+        eval_expr(self.graph, self.layout, obj_i, expr, &mut symbol)
+    }
+
+    /// 24 bit の値がセクション相対の場合、そのバンク (上位 8 bit) が参照先セクションの先頭と同じか検査する。
+    ///
+    /// 異なる場合、オフセットの加算でバンク境界を越えたか、セクションがバンク境界をまたいでいるので
+    /// 警告する。
+    fn check_far_bank(&self, obj: &Object, obj_sect_i: ObjSectIdx, off: usize, value: ExprValue) {
+        let Some(sect_i) = value.section_idx() else {
+            return;
+        };
+        let bank = self.layout.section(sect_i).start() >> 16;
+        if value.value() >> 16 != bank as i64 {
+            eprintln!(
+                "warning: '{}': section {obj_sect_i} offset ${off:04X}: 24-bit address ${:06X} is outside bank ${bank:02X} of the referenced section",
+                obj.name(),
+                value.value()
+            );
         }
     }
 }
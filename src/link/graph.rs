@@ -1,19 +1,29 @@
 use crate::index::{MemIdx, ObjIdx, ObjSectIdx, OutFileIdx, SectIdx, SegIdx};
 use crate::link::LinkScript;
 use crate::object::Object;
+use crate::script::{
+    LinkScriptFit, LinkScriptSectionSort, LinkScriptSegment, LinkScriptSegmentStart,
+};
+
+use super::layout::page_cross_pad;
 
 type FileToMems = Box<[Box<[MemIdx]>]>;
 type MemToSegs = Box<[Box<[SegIdx]>]>;
 type SegToSects = Box<[Box<[SectIdx]>]>;
 type ObjToSects = Box<[Box<[SectIdx]>]>;
 
-type MemToFile = Box<[OutFileIdx]>;
+type MemToFile = Box<[Option<OutFileIdx>]>;
 type SegToMem = Box<[MemIdx]>;
 type SectToSeg = Box<[SegIdx]>;
 
 type ObjSectToSect = Box<[Box<[Option<SectIdx>]>]>;
 type SectToObjSect = Box<[(ObjIdx, ObjSectIdx)]>;
 
+type SegToScriptSeg = Box<[SegIdx]>;
+
+// ca65 がデフォルトで出力するセグメント名。
+const PREDEF_SEG_NAMES: &[&str] = &["BSS", "CODE", "DATA", "NULL", "RODATA", "ZEROPAGE"];
+
 /// リンクに関与する要素 (ファイル、メモリ領域、セグメント...) 間の関係を保持する。
 ///
 /// ついでに要素の名前もここに持つ。
@@ -22,18 +32,22 @@ pub struct LinkGraph {
     file_to_mems: FileToMems,
     mem_to_segs: MemToSegs,
     seg_to_sects: SegToSects,
-    #[allow(dead_code)]
     obj_to_sects: ObjToSects,
 
-    #[allow(dead_code)]
     mem_to_file: MemToFile,
-    #[allow(dead_code)]
     seg_to_mem: SegToMem,
     sect_to_seg: SectToSeg,
 
     obj_sect_to_sect: ObjSectToSect,
     sect_to_obj_sect: SectToObjSect,
 
+    // 各セグメントの元になったリンカスクリプト上のセグメント。
+    // 分割配置されたセグメントの 2 つ目以降の部分、および複製されたセグメントの 2 つ目以降の複製を除き、
+    // 同じインデックスとなる。
+    seg_to_script_seg: SegToScriptSeg,
+    // 自動配置、または複製されたセグメント。
+    packed_segs: Box<[SegIdx]>,
+
     file_names: Box<[String]>,
     mem_names: Box<[String]>,
     seg_names: Box<[String]>,
@@ -79,6 +93,20 @@ impl LinkGraph {
         (0..self.file_count()).map(OutFileIdx::new)
     }
 
+    pub fn mems(&self) -> impl ExactSizeIterator<Item = MemIdx> + std::iter::FusedIterator + Clone {
+        (0..self.mem_count()).map(MemIdx::new)
+    }
+
+    pub fn segs(&self) -> impl ExactSizeIterator<Item = SegIdx> + std::iter::FusedIterator + Clone {
+        (0..self.seg_count()).map(SegIdx::new)
+    }
+
+    pub fn sects(
+        &self,
+    ) -> impl ExactSizeIterator<Item = SectIdx> + std::iter::FusedIterator + Clone {
+        (0..self.sect_count()).map(SectIdx::new)
+    }
+
     pub fn file_to_mems(
         &self,
         file_i: OutFileIdx,
@@ -100,7 +128,6 @@ impl LinkGraph {
         self.seg_to_sects[seg_i.get()].iter().copied()
     }
 
-    #[allow(dead_code)]
     pub fn obj_to_sects(
         &self,
         obj_i: ObjIdx,
@@ -108,17 +135,15 @@ impl LinkGraph {
         self.obj_to_sects[obj_i.get()].iter().copied()
     }
 
-    #[allow(dead_code)]
-    pub fn mem_to_file(&self, mem_i: MemIdx) -> OutFileIdx {
+    /// メモリ領域の出力先ファイルを返す。どのファイルにも出力されない場合は `None`。
+    pub fn mem_to_file(&self, mem_i: MemIdx) -> Option<OutFileIdx> {
         self.mem_to_file[mem_i.get()]
     }
 
-    #[allow(dead_code)]
     pub fn seg_to_mem(&self, seg_i: SegIdx) -> MemIdx {
         self.seg_to_mem[seg_i.get()]
     }
 
-    #[allow(dead_code)]
     pub fn sect_to_seg(&self, sect_i: SectIdx) -> SegIdx {
         self.sect_to_seg[sect_i.get()]
     }
@@ -131,20 +156,35 @@ impl LinkGraph {
         self.sect_to_obj_sect[sect_i.get()]
     }
 
+    /// セグメントの元になったリンカスクリプト上のセグメントを返す。
+    pub fn seg_to_script_seg(&self, seg_i: SegIdx) -> SegIdx {
+        self.seg_to_script_seg[seg_i.get()]
+    }
+
+    /// ロード先メモリ領域の候補が複数あり、自動配置されたセグメント (分割されたものは各部分、
+    /// 複製されたものは各複製) を列挙する。
+    pub fn packed_segs(
+        &self,
+    ) -> impl ExactSizeIterator<Item = SegIdx> + std::iter::FusedIterator + Clone {
+        self.packed_segs.iter().copied()
+    }
+
     pub fn new(script: &LinkScript, objs: &[Object]) -> Self {
         let (file_to_mems, mem_to_file) = Self::build_file_mem(script);
-        let (mem_to_segs, seg_to_mem) = Self::build_mem_seg(script);
+        // 自動配置にはセクションのサイズが必要なので、セクションとの関係を先に求める。
         let (seg_to_sects, obj_to_sects, sect_to_seg, obj_sect_to_sect, sect_to_obj_sect) =
             Self::build_seg_obj_sect(script, objs);
+        let (mem_to_segs, seg_to_mem, seg_to_sects, sect_to_seg, seg_to_script_seg, packed_segs) =
+            Self::build_mem_seg(script, objs, seg_to_sects, sect_to_seg, &sect_to_obj_sect);
 
         let file_names: Box<[_]> = script.iter_outfiles().map(str::to_owned).collect();
         let mem_names: Box<[_]> = script
             .iter_memorys()
             .map(|mem| mem.name().to_owned())
             .collect();
-        let seg_names: Box<[_]> = script
-            .iter_segments()
-            .map(|seg| seg.name().to_owned())
+        let seg_names: Box<[_]> = seg_to_script_seg
+            .iter()
+            .map(|&script_seg_i| script.segment(script_seg_i).name().to_owned())
             .collect();
 
         Self {
@@ -160,6 +200,9 @@ impl LinkGraph {
             obj_sect_to_sect,
             sect_to_obj_sect,
 
+            seg_to_script_seg,
+            packed_segs,
+
             file_names,
             mem_names,
             seg_names,
@@ -168,11 +211,14 @@ impl LinkGraph {
 
     pub fn build_file_mem(script: &LinkScript) -> (FileToMems, MemToFile) {
         let mut file_to_mems = vec![Vec::<MemIdx>::new(); script.outfile_count()];
-        let mut mem_to_file = Vec::<OutFileIdx>::with_capacity(script.memory_count());
+        let mut mem_to_file = Vec::<Option<OutFileIdx>>::with_capacity(script.memory_count());
 
+        // どのファイルにも出力されないメモリ領域は file_to_mems に含めない。
         for (mem_i, mem) in script.enumerate_memorys() {
             let file_i = mem.outfile_idx();
-            file_to_mems[file_i.get()].push(mem_i);
+            if let Some(file_i) = file_i {
+                file_to_mems[file_i.get()].push(mem_i);
+            }
             mem_to_file.push(file_i);
         }
 
@@ -182,20 +228,138 @@ impl LinkGraph {
         (file_to_mems, mem_to_file)
     }
 
-    pub fn build_mem_seg(script: &LinkScript) -> (MemToSegs, SegToMem) {
+    /// メモリ領域とセグメントの関係を求める。
+    ///
+    /// ロード先メモリ領域の候補が複数あるセグメントは、リンカスクリプト内の順に、空きのある候補に配置する。
+    /// 空きは、配置先が確定しているセグメントを先に置いた上で、実際のアドレスから求める (`MemoryUsage` を参照)。
+    ///
+    /// * 通常は fit 属性に従い、セグメント全体をいずれかの候補に配置する。
+    /// * split 属性がある場合、セクションを候補に順に詰めていき、使われたメモリ領域ごとの部分に分割する。
+    ///   2 つ目以降の部分は新たなセグメントとして末尾に追加され、セクションもそちらに移される。
+    /// * replicate 属性がある場合、全ての候補に配置する。2 つ目以降の複製は新たなセグメントとして
+    ///   末尾に追加されるが、セクションは元のセグメントに属したまま (複製は同じセクションを参照する)。
+    pub fn build_mem_seg(
+        script: &LinkScript,
+        objs: &[Object],
+        seg_to_sects: SegToSects,
+        sect_to_seg: SectToSeg,
+        sect_to_obj_sect: &[(ObjIdx, ObjSectIdx)],
+    ) -> (
+        MemToSegs,
+        SegToMem,
+        SegToSects,
+        SectToSeg,
+        SegToScriptSeg,
+        Box<[SegIdx]>,
+    ) {
         let mut mem_to_segs = vec![Vec::<SegIdx>::new(); script.memory_count()];
-        let mut seg_to_mem = Vec::<MemIdx>::with_capacity(script.segment_count());
+        let mut seg_to_mem: Vec<MemIdx> =
+            script.iter_segments().map(|seg| seg.memory_idx()).collect();
+        let mut seg_to_sects: Vec<Vec<SectIdx>> = seg_to_sects
+            .into_vec()
+            .into_iter()
+            .map(<[SectIdx]>::into_vec)
+            .collect();
+        let mut sect_to_seg = sect_to_seg.into_vec();
+        let mut seg_to_script_seg: Vec<SegIdx> =
+            (0..script.segment_count()).map(SegIdx::new).collect();
+        let mut packed_segs = Vec::<SegIdx>::new();
+
+        let usage = MemoryUsage {
+            script,
+            objs,
+            sect_to_obj_sect,
+        };
 
+        // 配置先が確定しているセグメントを先に置いておく (複製されるものは全ての候補に)。
         for (seg_i, seg) in script.enumerate_segments() {
-            let mem_i = seg.memory_idx();
-            mem_to_segs[mem_i.get()].push(seg_i);
-            seg_to_mem.push(mem_i);
+            if seg.memory_candidates().len() == 1 {
+                mem_to_segs[seg.memory_idx().get()].push(seg_i);
+            } else if seg.is_replicated() {
+                for (copy_k, mem_i) in seg.memory_candidates().enumerate() {
+                    // 最初の複製は元のセグメントとする。セクションは元のセグメントに属したままで、
+                    // 他の複製は同じセクションを参照する。
+                    let copy_i = if copy_k == 0 {
+                        seg_i
+                    } else {
+                        seg_to_mem.push(mem_i);
+                        seg_to_sects.push(seg_to_sects[seg_i.get()].clone());
+                        seg_to_script_seg.push(seg_i);
+                        SegIdx::new(seg_to_sects.len() - 1)
+                    };
+                    mem_to_segs[mem_i.get()].push(copy_i);
+                    packed_segs.push(copy_i);
+                }
+            }
         }
 
-        let mem_to_segs = vecvec_to_boxbox(mem_to_segs);
-        let seg_to_mem = seg_to_mem.into_boxed_slice();
+        for (seg_i, seg) in script.enumerate_segments() {
+            if seg.memory_candidates().len() == 1 || seg.is_replicated() {
+                continue;
+            }
 
-        (mem_to_segs, seg_to_mem)
+            if !seg.is_split() {
+                let (mem_i, pos) = pack_segment(
+                    &usage,
+                    &mem_to_segs,
+                    &seg_to_sects,
+                    &seg_to_script_seg,
+                    seg_i,
+                    &seg_to_sects[seg_i.get()],
+                );
+                mem_to_segs[mem_i.get()].insert(pos, seg_i);
+                seg_to_mem[seg_i.get()] = mem_i;
+                packed_segs.push(seg_i);
+            } else {
+                let sects = std::mem::take(&mut seg_to_sects[seg_i.get()]);
+                let parts = split_segment(
+                    &usage,
+                    &mem_to_segs,
+                    &seg_to_sects,
+                    &seg_to_script_seg,
+                    seg_i,
+                    &sects,
+                );
+                // 各部分は異なるメモリ領域に置かれるので、挿入位置は互いに影響しない。
+                for (part_k, (mem_i, pos, sects)) in parts.into_iter().enumerate() {
+                    // 最初の部分は元のセグメントとする。
+                    let part_i = if part_k == 0 {
+                        seg_i
+                    } else {
+                        seg_to_mem.push(mem_i);
+                        seg_to_sects.push(Vec::new());
+                        seg_to_script_seg.push(seg_i);
+                        SegIdx::new(seg_to_sects.len() - 1)
+                    };
+                    for &sect_i in &sects {
+                        sect_to_seg[sect_i.get()] = part_i;
+                    }
+                    seg_to_sects[part_i.get()] = sects;
+                    mem_to_segs[mem_i.get()].insert(pos, part_i);
+                    seg_to_mem[part_i.get()] = mem_i;
+                    packed_segs.push(part_i);
+                }
+            }
+        }
+
+        // 複製されたものを先に置いたので、リンカスクリプト内の順に並べ直す。
+        packed_segs.sort_by_key(|&seg_i| (seg_to_script_seg[seg_i.get()], seg_i));
+
+        (
+            vecvec_to_boxbox(mem_to_segs),
+            seg_to_mem.into_boxed_slice(),
+            vecvec_to_boxbox(seg_to_sects),
+            sect_to_seg.into_boxed_slice(),
+            seg_to_script_seg.into_boxed_slice(),
+            packed_segs.into_boxed_slice(),
+        )
+    }
+
+    /// セクションが属するセグメントとしてリンカスクリプトに記述が必要かどうかを返す。
+    ///
+    /// ca65 がデフォルトで生成するセグメント (CODE など) は、空ならば記述がなくてもよい。
+    pub fn needs_segment(seg_name: &str, obj_sect_is_empty: bool) -> bool {
+        !(obj_sect_is_empty && PREDEF_SEG_NAMES.contains(&seg_name))
     }
 
     pub fn build_seg_obj_sect(
@@ -208,9 +372,6 @@ impl LinkGraph {
         ObjSectToSect,
         SectToObjSect,
     ) {
-        // ca65 がデフォルトで出力するセグメント名。
-        const PREDEF_SEG_NAMES: &[&str] = &["BSS", "CODE", "DATA", "NULL", "RODATA", "ZEROPAGE"];
-
         let mut seg_to_sects = vec![Vec::<SectIdx>::new(); script.segment_count()];
         let mut obj_to_sects = vec![Vec::<SectIdx>::new(); objs.len()];
         let mut sect_to_seg = Vec::<SegIdx>::new();
@@ -263,6 +424,11 @@ impl LinkGraph {
             obj_sect_to_sect.push(obj_sect_to_sect_row);
         }
 
+        // order/sort 属性に従い、各セグメント内のセクションを並べ替える。
+        for (seg_i, seg) in script.enumerate_segments() {
+            sort_sects(seg, objs, &sect_to_obj_sect, &mut seg_to_sects[seg_i.get()]);
+        }
+
         let seg_to_sects = vecvec_to_boxbox(seg_to_sects);
         let obj_to_sects = vecvec_to_boxbox(obj_to_sects);
         let sect_to_seg = sect_to_seg.into_boxed_slice();
@@ -279,6 +445,468 @@ impl LinkGraph {
     }
 }
 
+/// ロード先メモリ領域の候補が複数あるセグメント `script_seg_i` (セクションは `sects`) の配置先を
+/// fit 属性に従って選び、(メモリ領域, `mem_to_segs` 内の挿入位置) を返す。
+///
+/// どの候補にも収まらなければ panic する。
+fn pack_segment(
+    usage: &MemoryUsage,
+    mem_to_segs: &[Vec<SegIdx>],
+    seg_to_sects: &[Vec<SectIdx>],
+    seg_to_script_seg: &[SegIdx],
+    script_seg_i: SegIdx,
+    sects: &[SectIdx],
+) -> (MemIdx, usize) {
+    let seg = usage.script.segment(script_seg_i);
+    let entries_of =
+        |mem_i: MemIdx| memory_entries(&mem_to_segs[mem_i.get()], seg_to_sects, seg_to_script_seg);
+
+    let mut fits = seg.memory_candidates().filter_map(|mem_i| {
+        usage
+            .find_slot(mem_i, &entries_of(mem_i), script_seg_i, sects)
+            .map(|(pos, free)| (mem_i, pos, free))
+    });
+    let found = match seg.fit() {
+        LinkScriptFit::First => fits.next(),
+        LinkScriptFit::Best => fits.min_by_key(|&(_, _, free)| free),
+    };
+
+    let Some((mem_i, pos, _)) = found else {
+        let candidates: Vec<_> = seg
+            .memory_candidates()
+            .map(|mem_i| {
+                format!(
+                    "'{}' (free ${:04X})",
+                    usage.script.memory(mem_i).name(),
+                    usage.free(mem_i, entries_of(mem_i)).unwrap_or(0)
+                )
+            })
+            .collect();
+        panic!(
+            "segment '{}' (size ${:04X}) does not fit in any of memory {}",
+            seg.name(),
+            usage.sects_len(sects),
+            candidates.join(", ")
+        )
+    };
+
+    (mem_i, pos)
+}
+
+/// split 属性のあるセグメント `script_seg_i` のセクション `sects` を、候補のメモリ領域に順に詰めていく。
+///
+/// 使われたメモリ領域、`mem_to_segs` 内の挿入位置、そこに置かれるセクションの組を候補の順に返す
+/// (セクションがない場合、最初の候補に空の部分を 1 つ置く)。収まらないセクションがあれば panic する。
+fn split_segment(
+    usage: &MemoryUsage,
+    mem_to_segs: &[Vec<SegIdx>],
+    seg_to_sects: &[Vec<SectIdx>],
+    seg_to_script_seg: &[SegIdx],
+    script_seg_i: SegIdx,
+    sects: &[SectIdx],
+) -> Vec<(MemIdx, usize, Vec<SectIdx>)> {
+    let seg = usage.script.segment(script_seg_i);
+    let entries_of =
+        |mem_i: MemIdx| memory_entries(&mem_to_segs[mem_i.get()], seg_to_sects, seg_to_script_seg);
+    let candidates: Vec<_> = seg.memory_candidates().collect();
+
+    let mut parts = Vec::<(MemIdx, usize, Vec<SectIdx>)>::new();
+    let mut k = 0;
+    let mut entries = entries_of(candidates[0]);
+    // 現在の候補に置く部分と、その挿入位置 (空でなければ常に `Some`)。
+    let mut part = Vec::<SectIdx>::new();
+    let mut part_pos = None::<usize>;
+
+    for &sect_i in sects {
+        part.push(sect_i);
+        // 一度次の候補に移ったら、前の候補には戻らない (セクションの順序を保つため)。
+        loop {
+            if let Some((pos, _)) = usage.find_slot(candidates[k], &entries, script_seg_i, &part) {
+                part_pos = Some(pos);
+                break;
+            }
+            part.pop();
+            // 空き容量が足りず飛ばされた候補は含めない。
+            if let Some(pos) = part_pos.take() {
+                parts.push((candidates[k], pos, std::mem::take(&mut part)));
+            }
+            k += 1;
+            if k == candidates.len() {
+                let (obj_i, obj_sect_i) = usage.sect_to_obj_sect[sect_i.get()];
+                panic!(
+                    "segment '{}': section {obj_sect_i} of '{}' (size ${:04X}) does not fit in any of the remaining memory areas",
+                    seg.name(),
+                    usage.objs[obj_i.get()].name(),
+                    usage.sects_len(&[sect_i])
+                );
+            }
+            entries = entries_of(candidates[k]);
+            part.push(sect_i);
+        }
+    }
+    if let Some(pos) = part_pos {
+        parts.push((candidates[k], pos, part));
+    }
+
+    if parts.is_empty() {
+        let entries = entries_of(candidates[0]);
+        let pos = usage
+            .find_slot(candidates[0], &entries, script_seg_i, &[])
+            .map_or(entries.len(), |(pos, _)| pos);
+        parts.push((candidates[0], pos, Vec::new()));
+    }
+
+    parts
+}
+
+/// 自動配置のための、メモリ領域内に置かれたセグメントのアドレスの見積もり。
+///
+/// `LinkLayout` と同じ規則 (開始アドレス指定、ページ境界をまたがないためのパディング) でアドレスを
+/// 計算するので、開始アドレス指定のあるセグメントの手前の隙間なども正しく扱える。
+/// アラインメントは `LinkLayout` と同様に未対応で、1 以外が指定されていれば panic する。
+struct MemoryUsage<'a> {
+    script: &'a LinkScript,
+    objs: &'a [Object<'a>],
+    sect_to_obj_sect: &'a [(ObjIdx, ObjSectIdx)],
+}
+
+/// メモリ領域内に置かれたセグメント (リンカスクリプト上のセグメント, セクション)。
+type MemoryEntry<'s> = (SegIdx, &'s [SectIdx]);
+
+impl MemoryUsage<'_> {
+    /// メモリ領域 `mem_i` にセグメントを `entries` の順に置いたときの空き容量 (セグメント間の隙間を含む)
+    /// を返す。開始アドレス指定のあるセグメントと重なる場合や、メモリ領域に収まらない場合は `None`。
+    fn free<'s, I>(&self, mem_i: MemIdx, entries: I) -> Option<usize>
+    where
+        I: IntoIterator<Item = MemoryEntry<'s>>,
+    {
+        let mem = self.script.memory(mem_i);
+        let mut addr = mem.start();
+        let mut used = 0;
+
+        for (script_seg_i, sects) in entries {
+            let seg = self.script.segment(script_seg_i);
+            match seg.start() {
+                LinkScriptSegmentStart::Unspecified => {}
+                LinkScriptSegmentStart::Addr(start) => {
+                    if addr > start {
+                        return None;
+                    }
+                    addr = start;
+                }
+                LinkScriptSegmentStart::Align(align) => {
+                    assert_eq!(
+                        align,
+                        1,
+                        "segment '{}': alignment is not supported",
+                        seg.name()
+                    );
+                }
+            }
+            let seg_start = addr;
+
+            for &sect_i in sects {
+                let (obj_i, obj_sect_i) = self.sect_to_obj_sect[sect_i.get()];
+                let obj = &self.objs[obj_i.get()];
+                let obj_sect = obj.section(obj_sect_i);
+                assert_eq!(
+                    obj_sect.align(),
+                    1,
+                    "'{}': section {obj_sect_i}: alignment is not supported",
+                    obj.name()
+                );
+                let len = obj_sect.len() as usize;
+                if !seg.allows_page_cross() {
+                    addr += page_cross_pad(addr, len);
+                }
+                addr += len;
+            }
+
+            used += addr - seg_start;
+        }
+
+        (addr <= mem.range().max() + 1).then(|| mem.len() - used)
+    }
+
+    /// セグメント `script_seg_i` のセクション `sects` を、既に `entries` が置かれたメモリ領域 `mem_i`
+    /// のどこに置くかを決め、(`entries` 内の挿入位置, 置いた後の空き容量) を返す。収まらなければ `None`。
+    ///
+    /// リンカスクリプト内の順序どおりの位置を優先し、そこに置けなければ開始アドレス指定のあるセグメントの
+    /// 手前の隙間を前から順に探す (他のセグメントの順序は変えない)。
+    fn find_slot(
+        &self,
+        mem_i: MemIdx,
+        entries: &[MemoryEntry],
+        script_seg_i: SegIdx,
+        sects: &[SectIdx],
+    ) -> Option<(usize, usize)> {
+        let script_pos = entries
+            .iter()
+            .rposition(|&(other_i, _)| other_i <= script_seg_i)
+            .map_or(0, |pos| pos + 1);
+
+        let gaps = (0..entries.len()).filter(|&pos| {
+            let (other_i, _) = entries[pos];
+            pos != script_pos
+                && matches!(
+                    self.script.segment(other_i).start(),
+                    LinkScriptSegmentStart::Addr(_)
+                )
+        });
+
+        std::iter::once(script_pos).chain(gaps).find_map(|pos| {
+            let (before, after) = entries.split_at(pos);
+            let entries = before
+                .iter()
+                .copied()
+                .chain([(script_seg_i, sects)])
+                .chain(after.iter().copied());
+            self.free(mem_i, entries).map(|free| (pos, free))
+        })
+    }
+
+    /// セクションのサイズの合計を返す (パディングは含まない)。
+    fn sects_len(&self, sects: &[SectIdx]) -> usize {
+        sects
+            .iter()
+            .map(|sect_i| {
+                let (obj_i, obj_sect_i) = self.sect_to_obj_sect[sect_i.get()];
+                self.objs[obj_i.get()].section(obj_sect_i).len() as usize
+            })
+            .sum()
+    }
+}
+
+/// `mem_to_segs` の 1 つのメモリ領域分を `MemoryUsage` の入力に変換する。
+fn memory_entries<'s>(
+    mem_segs: &[SegIdx],
+    seg_to_sects: &'s [Vec<SectIdx>],
+    seg_to_script_seg: &[SegIdx],
+) -> Vec<MemoryEntry<'s>> {
+    mem_segs
+        .iter()
+        .map(|seg_i| {
+            (
+                seg_to_script_seg[seg_i.get()],
+                seg_to_sects[seg_i.get()].as_slice(),
+            )
+        })
+        .collect()
+}
+
+/// セグメント内のセクションを、order 属性のグループ順、sort 属性の順に並べ替える。
+///
+/// どちらでも順序が決まらないものはオブジェクトファイルの順のままとする。
+fn sort_sects(
+    seg: &LinkScriptSegment,
+    objs: &[Object],
+    sect_to_obj_sect: &[(ObjIdx, ObjSectIdx)],
+    sects: &mut [SectIdx],
+) {
+    let obj_of = |sect_i: SectIdx| &objs[sect_to_obj_sect[sect_i.get()].0.get()];
+
+    // 安定ソートなので、sort 属性の順に並べてから order 属性のグループ順に並べればよい。
+    match seg.section_sort() {
+        LinkScriptSectionSort::Object => {}
+        LinkScriptSectionSort::Name => sects.sort_by_key(|&sect_i| obj_of(sect_i).name()),
+        LinkScriptSectionSort::Size => sects.sort_by_key(|&sect_i| {
+            let (obj_i, obj_sect_i) = sect_to_obj_sect[sect_i.get()];
+            std::cmp::Reverse(objs[obj_i.get()].section(obj_sect_i).len())
+        }),
+    }
+    sects.sort_by_key(|&sect_i| seg.order_rank(obj_of(sect_i).name()));
+}
+
 fn vecvec_to_boxbox<T>(vv: Vec<Vec<T>>) -> Box<[Box<[T]>]> {
     vv.into_iter().map(Vec::into_boxed_slice).collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::link::link;
+    use crate::script::LinkScript;
+    use crate::testutil::{parse_object, TestObject};
+
+    /// セグメントごとに指定サイズのセクションを 1 つずつ持つオブジェクトファイルを作る。
+    fn object_with_segments(segs: &[(&str, usize)]) -> Vec<u8> {
+        let mut obj = TestObject::new("main");
+        for &(seg_name, len) in segs {
+            let sect_i = obj.section(seg_name);
+            obj.literal(sect_i, &vec![0xEA; len]);
+        }
+        obj.encode()
+    }
+
+    #[test]
+    fn pack_segment_uses_gap_before_fixed_segment() {
+        // VECTORS より後ろには空きがないので、CODE はその手前の隙間に置かれなければならない。
+        let script = LinkScript::load(
+            r#"
+MEMORY {
+    ROM1: start = $8000, size = $100;
+    ROM2: start = $9000, size = $100;
+}
+SEGMENTS {
+    VECTORS: load = ROM1, start = $80FA;
+    CODE:    load = ROM1|ROM2;
+}
+"#,
+            "out.bin",
+        )
+        .unwrap();
+        let bytes = object_with_segments(&[("VECTORS", 6), ("CODE", 0xF0)]);
+        let objs = [parse_object("main", &bytes)];
+
+        let result = link(&script, &objs);
+        let code = result.segment("CODE").unwrap();
+        assert_eq!(code.memory_name(), "ROM1");
+        assert_eq!(code.start(), 0x8000);
+        assert_eq!(result.segment("VECTORS").unwrap().start(), 0x80FA);
+    }
+
+    #[test]
+    fn pack_segment_skips_memory_fragmented_by_fixed_segment() {
+        // ROM1 の空き容量の合計は足りるが、VECTORS の前後どちらの隙間にも収まらない。
+        let script = LinkScript::load(
+            r#"
+MEMORY {
+    ROM1: start = $8000, size = $100;
+    ROM2: start = $9000, size = $100;
+}
+SEGMENTS {
+    VECTORS: load = ROM1, start = $8080;
+    CODE:    load = ROM1|ROM2;
+}
+"#,
+            "out.bin",
+        )
+        .unwrap();
+        let bytes = object_with_segments(&[("VECTORS", 6), ("CODE", 0x90)]);
+        let objs = [parse_object("main", &bytes)];
+
+        let result = link(&script, &objs);
+        let code = result.segment("CODE").unwrap();
+        assert_eq!(code.memory_name(), "ROM2");
+        assert_eq!(code.start(), 0x9000);
+    }
+
+    #[test]
+    fn pack_segment_counts_page_padding() {
+        // DATA のセクションはページ境界をまたげないので、$80 バイトのパディングが入り ROM1 に収まらない。
+        let script = LinkScript::load(
+            r#"
+MEMORY {
+    ROM1: start = $8000, size = $180;
+    ROM2: start = $9000, size = $200;
+}
+SEGMENTS {
+    CODE: load = ROM1;
+    DATA: load = ROM1|ROM2, pagecross = no;
+}
+"#,
+            "out.bin",
+        )
+        .unwrap();
+        let bytes = object_with_segments(&[("CODE", 0x80), ("DATA", 0x100)]);
+        let objs = [parse_object("main", &bytes)];
+
+        let result = link(&script, &objs);
+        let data = result.segment("DATA").unwrap();
+        assert_eq!(data.memory_name(), "ROM2");
+        assert_eq!(data.start(), 0x9000);
+    }
+
+    #[test]
+    fn split_segment_stops_before_fixed_segment() {
+        // ROM1 の空き容量の合計 ($FA) には収まるが、VECTORS の手前の隙間 ($F0) には c.o の分が収まらない。
+        let script = LinkScript::load(
+            r#"
+MEMORY {
+    ROM1: start = $8000, size = $100;
+    ROM2: start = $9000, size = $100;
+}
+SEGMENTS {
+    VECTORS: load = ROM1, start = $80F0;
+    CODE:    load = ROM1|ROM2, split = yes;
+}
+"#,
+            "out.bin",
+        )
+        .unwrap();
+        let bytes = [
+            object_with_segments(&[("VECTORS", 6), ("CODE", 0x80)]),
+            object_with_segments(&[("CODE", 0x60)]),
+            object_with_segments(&[("CODE", 0x18)]),
+        ];
+        let objs: Vec<_> = ["a", "b", "c"]
+            .into_iter()
+            .zip(&bytes)
+            .map(|(name, bytes)| parse_object(name, bytes))
+            .collect();
+
+        let result = link(&script, &objs);
+        let code_start = |obj_name: &str| {
+            result
+                .object_sections(obj_name)
+                .unwrap()
+                .find(|sect| sect.segment_name() == "CODE")
+                .unwrap()
+                .start()
+        };
+        assert_eq!(code_start("a.o"), 0x8000);
+        assert_eq!(code_start("b.o"), 0x8080);
+        assert_eq!(code_start("c.o"), 0x9000);
+        assert_eq!(result.segment("VECTORS").unwrap().start(), 0x80F0);
+    }
+
+    #[test]
+    fn split_segment_skips_full_candidate() {
+        // ROM1 は CODE で埋まっているので、DATA は全て ROM2 に置かれる。
+        let script = LinkScript::load(
+            r#"
+MEMORY {
+    ROM1: start = $8000, size = $100;
+    ROM2: start = $9000, size = $100;
+}
+SEGMENTS {
+    CODE: load = ROM1;
+    DATA: load = ROM1|ROM2, split = yes;
+}
+"#,
+            "out.bin",
+        )
+        .unwrap();
+        let bytes = object_with_segments(&[("CODE", 0x100), ("DATA", 0x10)]);
+        let objs = [parse_object("main", &bytes)];
+
+        let result = link(&script, &objs);
+        let datas: Vec<_> = result
+            .iter_segments()
+            .filter(|seg| seg.name() == "DATA")
+            .map(|seg| (seg.memory_name(), seg.start()))
+            .collect();
+        assert_eq!(datas, [("ROM2", 0x9000)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "segment 'DATA': alignment is not supported")]
+    fn pack_segment_rejects_alignment() {
+        let script = LinkScript::load(
+            r#"
+MEMORY {
+    ROM1: start = $8000, size = $100;
+    ROM2: start = $9000, size = $100;
+}
+SEGMENTS {
+    DATA: load = ROM1|ROM2, align = $10;
+}
+"#,
+            "out.bin",
+        )
+        .unwrap();
+        let bytes = object_with_segments(&[("DATA", 0x10)]);
+        let objs = [parse_object("main", &bytes)];
+
+        link(&script, &objs);
+    }
+}
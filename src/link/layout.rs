@@ -1,7 +1,9 @@
+use xo65::section::{Section, SectionFragmentBody};
+
 use crate::index::{MemIdx, OutFileIdx, SectIdx, SegIdx};
 use crate::object::Object;
 use crate::range::NonemptyRange;
-use crate::script::{LinkScript, LinkScriptSegmentStart};
+use crate::script::{LinkScript, LinkScriptSegmentStart, LinkScriptSegmentType};
 
 use super::graph::LinkGraph;
 
@@ -32,104 +34,215 @@ impl LinkLayout {
     }
 
     pub fn new(script: &LinkScript, objs: &[Object], graph: &LinkGraph) -> Self {
-        let mut files = vec![None::<LinkLayoutFile>; graph.file_count()];
         let mut mems = vec![None::<LinkLayoutMemory>; graph.mem_count()];
         let mut segs = vec![None::<LinkLayoutSegment>; graph.seg_count()];
         let mut sects = vec![None::<LinkLayoutSection>; graph.sect_count()];
 
-        // 各ファイルを根とする木を辿り、レイアウトを決定する。
-        for file_i in graph.files() {
-            let mut file_off = 0;
-
-            for mem_i in graph.file_to_mems(file_i) {
-                let script_mem = script.memory(mem_i);
-                let mut addr = script_mem.start();
-                let mut layout_mem = LinkLayoutMemory {
-                    file_off,
-                    range: script_mem.range(),
+        // 各ファイルの現在のサイズ (次のメモリ領域のファイル内オフセット)。
+        let mut file_lens = vec![0_usize; graph.file_count()];
+
+        // 各メモリ領域を根とする木を辿り、レイアウトを決定する。
+        // メモリ領域はリンカスクリプト内の順に処理するので、各ファイル内の順序も保たれる。
+        for mem_i in graph.mems() {
+            let file_i = graph.mem_to_file(mem_i);
+            let file_off = file_i.map_or(0, |file_i| file_lens[file_i.get()]);
+
+            let script_mem = script.memory(mem_i);
+            let mut addr = script_mem.start();
+            let mut layout_mem = LinkLayoutMemory {
+                file_off,
+                range: script_mem.range(),
+                output_len: 0, // 未計算
+                used: 0,       // 未計算
+                filled: script_mem.is_filled(),
+                fill_byte: script_mem.fill_byte(),
+                bank: script_mem.bank(),
+            };
+
+            for seg_i in graph.mem_to_segs(mem_i) {
+                let script_seg = script.segment(graph.seg_to_script_seg(seg_i));
+                let bss = script_seg.is_bss();
+                // セグメントの開始アドレスを決定。
+                match script_seg.start() {
+                    LinkScriptSegmentStart::Unspecified => {}
+                    LinkScriptSegmentStart::Addr(start) => {
+                        // 前のセグメントと重なってはならない。
+                        assert!(
+                            addr <= start,
+                            "segment '{}' overwrites another segment",
+                            graph.seg_name(seg_i)
+                        );
+                        addr = start;
+                    }
+                    LinkScriptSegmentStart::Align(align) => {
+                        assert_eq!(
+                            align,
+                            1,
+                            "segment '{}': alignment is not supported",
+                            graph.seg_name(seg_i)
+                        );
+                    }
+                }
+                let mut layout_seg = LinkLayoutSegment {
+                    start: addr,
+                    len: 0,        // 未計算
                     output_len: 0, // 未計算
-                    filled: script_mem.is_filled(),
-                    fill_byte: script_mem.fill_byte(),
+                    fill_byte: script_seg.fill_byte(),
                 };
 
-                for seg_i in graph.mem_to_segs(mem_i) {
-                    let script_seg = script.segment(seg_i);
-                    let bss = script_seg.is_bss();
-                    // セグメントの開始アドレスを決定。
-                    match script_seg.start() {
-                        LinkScriptSegmentStart::Unspecified => {}
-                        LinkScriptSegmentStart::Addr(start) => {
-                            // 前のセグメントと重なってはならない。
-                            assert!(
-                                addr <= start,
-                                "segment '{}' overwrites another segment",
-                                graph.seg_name(seg_i)
-                            );
-                            addr = start;
-                        }
-                        LinkScriptSegmentStart::Align(align) => {
-                            assert_eq!(
-                                align,
-                                1,
-                                "segment '{}': alignment is not supported",
-                                graph.seg_name(seg_i)
-                            );
-                        }
+                for sect_i in graph.seg_to_sects(seg_i) {
+                    // 複製されたセグメントの場合、セクションのレイアウトは元のセグメントで決める。
+                    // (複製ではアドレスとサイズのみ計上する)
+                    let owned = graph.sect_to_seg(sect_i) == seg_i;
+                    let (obj_i, obj_sect_i) = graph.sect_to_obj_sect(sect_i);
+                    let obj = &objs[obj_i.get()];
+                    let obj_sect = obj.section(obj_sect_i);
+                    assert_eq!(
+                        obj_sect.align(),
+                        1,
+                        "'{}': section {obj_sect_i}: alignment is not supported",
+                        obj.name()
+                    );
+
+                    if owned && bss && has_initialized_data(obj_sect) {
+                        eprintln!(
+                            "warning: '{}': segment '{}' with type '{}' contains initialized data",
+                            obj.name(),
+                            graph.seg_name(seg_i),
+                            if script_seg.ty() == LinkScriptSegmentType::Zp {
+                                "zp"
+                            } else {
+                                "bss"
+                            },
+                        );
                     }
-                    let mut layout_seg = LinkLayoutSegment {
-                        start: addr,
-                        output_len: 0, // 未計算
-                        fill_byte: script_seg.fill_byte(),
-                    };
 
-                    for sect_i in graph.seg_to_sects(seg_i) {
-                        let (obj_i, obj_sect_i) = graph.sect_to_obj_sect(sect_i);
-                        let obj = &objs[obj_i.get()];
-                        let obj_sect = obj.section(obj_sect_i);
-                        assert_eq!(
-                            obj_sect.align(),
-                            1,
-                            "'{}': section {obj_sect_i}: alignment is not supported",
-                            obj.name()
+                    // NOTE: BSS の場合、実際の出力サイズは 0 (アドレス加算のみ行うことになる)。
+                    let sect_len = obj_sect.len() as usize;
+
+                    // ページ境界をまたいではならないセクションは、必要なら次のページの先頭へ移す。
+                    let page_pad = if !script_seg.allows_page_cross() {
+                        assert!(
+                            sect_len <= 0x100,
+                            "'{}': section {obj_sect_i} (size ${sect_len:04X}) in segment '{}' is larger than a page",
+                            obj.name(),
+                            graph.seg_name(seg_i)
                         );
+                        page_cross_pad(addr, sect_len)
+                    } else {
+                        0
+                    };
+                    if page_pad > 0 {
+                        layout_seg.len += page_pad;
+                        if !bss {
+                            layout_seg.output_len += page_pad;
+                        }
+                        addr += page_pad;
+                    }
 
-                        // NOTE: BSS の場合、実際の出力サイズは 0 (アドレス加算のみ行うことになる)。
-                        let sect_len = obj_sect.len() as usize;
-                        let output_len = if bss { 0 } else { sect_len };
-                        let layout_sect = LinkLayoutSection {
-                            start: addr,
-                            output_len,
-                        };
+                    // ファイルへ出力されないメモリ領域に実データを置くことはできない。
+                    assert!(
+                        bss || sect_len == 0 || file_i.is_some(),
+                        "segment '{}' has data but memory '{}' is not written to any file",
+                        graph.seg_name(seg_i),
+                        graph.mem_name(mem_i)
+                    );
+                    let output_len = if bss { 0 } else { sect_len };
+                    let layout_sect = LinkLayoutSection {
+                        start: addr,
+                        output_len,
+                        page_pad,
+                    };
+                    if owned {
                         sects[sect_i.get()] = Some(layout_sect);
+                    }
 
-                        layout_seg.output_len += output_len;
-                        layout_mem.output_len += output_len;
-
-                        assert!(
-                            layout_mem.output_len <= script_mem.len(),
-                            "memory '{}' overflows",
-                            graph.mem_name(mem_i)
+                    // 65816 ではプログラムカウンタがバンク内で循環するので、バンク境界 (64 KiB) を
+                    // またぐセクションは (データであっても) 報告する。
+                    if owned && sect_len > 0 && addr >> 16 != (addr + sect_len - 1) >> 16 {
+                        let msg = format!(
+                            "'{}': section {obj_sect_i} in segment '{}' crosses a bank boundary at ${:06X}",
+                            obj.name(),
+                            graph.seg_name(seg_i),
+                            (addr + sect_len - 1) & !0xFFFF
                         );
+                        assert!(script_mem.allows_bank_cross(), "{msg}");
+                        eprintln!("warning: {msg}");
+                    }
 
-                        addr += sect_len;
+                    layout_seg.len += sect_len;
+                    layout_seg.output_len += output_len;
+                    // メモリ領域の出力は最後のデータまで (開始アドレス指定による隙間も含む)。
+                    if output_len > 0 {
+                        layout_mem.output_len = addr + output_len - script_mem.start();
                     }
 
-                    segs[seg_i.get()] = Some(layout_seg);
+                    assert!(
+                        layout_mem.output_len <= script_mem.len(),
+                        "memory '{}' overflows",
+                        graph.mem_name(mem_i)
+                    );
+
+                    addr += sect_len;
                 }
 
-                if layout_mem.filled {
-                    layout_mem.output_len = script_mem.len();
+                // セグメントはメモリ領域の末尾を超えてはならない (ファイルへ出力されない BSS も含む)。
+                assert!(
+                    addr <= script_mem.range().max() + 1,
+                    "segment '{}' overflows memory '{}' by ${:04X} bytes",
+                    graph.seg_name(seg_i),
+                    graph.mem_name(mem_i),
+                    addr - (script_mem.range().max() + 1)
+                );
+
+                // ゼロページセグメントは $FF を超えてはならない。
+                if script_seg.ty() == LinkScriptSegmentType::Zp {
+                    assert!(
+                        layout_seg.start <= 0xFF && addr <= 0x100,
+                        "segment '{}': zeropage segment is out of range $00-$FF",
+                        graph.seg_name(seg_i)
+                    );
                 }
 
-                file_off += layout_mem.output_len;
+                segs[seg_i.get()] = Some(layout_seg);
+            }
+
+            // アドレスは単調に増えるので、最後のアドレスが最も後ろにあるセグメントの終端となる。
+            layout_mem.used = addr - script_mem.start();
+
+            // ファイルへ出力されないメモリ領域は fill 指定を無視する。
+            if layout_mem.filled && file_i.is_some() {
+                layout_mem.output_len = script_mem.len();
+            }
 
-                mems[mem_i.get()] = Some(layout_mem);
+            if let Some(file_i) = file_i {
+                file_lens[file_i.get()] += layout_mem.output_len;
             }
 
-            files[file_i.get()] = Some(LinkLayoutFile { len: file_off });
+            mems[mem_i.get()] = Some(layout_mem);
+        }
+
+        // 複製されたセグメントは、全ての複製が同じアドレスに置かれなければならない。
+        for seg_i in graph.segs() {
+            let orig_i = graph.seg_to_script_seg(seg_i);
+            if seg_i == orig_i || !script.segment(orig_i).is_replicated() {
+                continue;
+            }
+            let start = segs[seg_i.get()].as_ref().unwrap().start;
+            let orig_start = segs[orig_i.get()].as_ref().unwrap().start;
+            assert!(
+                start == orig_start,
+                "segment '{}': replicated at ${start:04X} in memory '{}' but at ${orig_start:04X} in memory '{}' (use the start attribute)",
+                graph.seg_name(seg_i),
+                graph.mem_name(graph.seg_to_mem(seg_i)),
+                graph.mem_name(graph.seg_to_mem(orig_i))
+            );
         }
 
-        let files: Box<[_]> = files.into_iter().map(Option::unwrap).collect();
+        let files: Box<[_]> = file_lens
+            .into_iter()
+            .map(|len| LinkLayoutFile { len })
+            .collect();
         let mems: Box<[_]> = mems.into_iter().map(Option::unwrap).collect();
         let segs: Box<[_]> = segs.into_iter().map(Option::unwrap).collect();
         let sects: Box<[_]> = sects.into_iter().map(Option::unwrap).collect();
@@ -143,6 +256,24 @@ impl LinkLayout {
     }
 }
 
+/// `addr` から始まる `len` バイトがページ境界をまたぐ場合、次のページの先頭までのバイト数を返す。
+/// またがない場合は 0 を返す。
+pub(super) fn page_cross_pad(addr: usize, len: usize) -> usize {
+    if len == 0 || addr / 0x100 == (addr + len - 1) / 0x100 {
+        0
+    } else {
+        0x100 - addr % 0x100
+    }
+}
+
+/// セクションが初期値を持つデータを含むかどうかを返す。
+fn has_initialized_data(obj_sect: &Section) -> bool {
+    obj_sect
+        .fragments()
+        .iter()
+        .any(|frag| !matches!(frag.body(), SectionFragmentBody::Fill(_)))
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct LinkLayoutFile {
     /// 実際の出力ファイルサイズ (0 のことも一応ある)。
@@ -168,8 +299,12 @@ pub struct LinkLayoutMemory {
     range: NonemptyRange,
     /// 実際にファイルへ出力されるサイズ (0 のこともある)。
     output_len: usize,
+    /// 先頭から、最も後ろにあるセグメントの終端までのサイズ。
+    used: usize,
     filled: bool,
     fill_byte: u8,
+    /// バンク番号 (リンカスクリプトの bank 属性)。
+    bank: Option<u32>,
 }
 
 impl LinkLayoutMemory {
@@ -177,7 +312,6 @@ impl LinkLayoutMemory {
         self.file_off
     }
 
-    #[allow(dead_code)]
     pub fn range(&self) -> NonemptyRange {
         self.range
     }
@@ -194,6 +328,12 @@ impl LinkLayoutMemory {
         self.output_len == 0
     }
 
+    /// 先頭から、最も後ろにあるセグメントの終端までのサイズを返す
+    /// (BSS、セグメント間の隙間、ページ境界のパディングも含む)。
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
     #[allow(dead_code)]
     pub fn is_filled(&self) -> bool {
         self.filled
@@ -202,12 +342,19 @@ impl LinkLayoutMemory {
     pub fn fill_byte(&self) -> u8 {
         self.fill_byte
     }
+
+    /// バンク番号を返す。bank 属性がない場合は `None`。
+    pub fn bank(&self) -> Option<u32> {
+        self.bank
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct LinkLayoutSegment {
     /// 開始アドレス。
     start: usize,
+    /// アドレス空間内に占めるサイズ (BSS の場合も含む。0 のこともある)。
+    len: usize,
     /// 実際にファイルへ出力されるサイズ (セクション間のパディングなども含む。0 のこともある)。
     output_len: usize,
     fill_byte: Option<u8>,
@@ -218,6 +365,12 @@ impl LinkLayoutSegment {
         self.start
     }
 
+    /// アドレス空間内に占めるサイズを返す。
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
     pub fn output_len(&self) -> usize {
         self.output_len
     }
@@ -237,6 +390,8 @@ pub struct LinkLayoutSection {
     start: usize,
     /// 実際にファイルへ出力されるサイズ (オブジェクトファイル内の値と同じ。0 のこともある)。
     output_len: usize,
+    /// ページ境界をまたがないよう、直前に挿入されたパディングのサイズ。
+    page_pad: usize,
 }
 
 impl LinkLayoutSection {
@@ -251,4 +406,69 @@ impl LinkLayoutSection {
     pub fn output_is_empty(&self) -> bool {
         self.output_len == 0
     }
+
+    /// ページ境界をまたがないよう、直前に挿入されたパディングのサイズを返す。
+    pub fn page_pad(&self) -> usize {
+        self.page_pad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::link::link;
+    use crate::script::LinkScript;
+    use crate::testutil::{parse_object, TestObject};
+
+    fn link_across_bank_boundary(bankcross: &str) {
+        let script = LinkScript::load(
+            &format!(
+                "MEMORY {{ ROM: start = $FF80, size = $100{bankcross}; }}\nSEGMENTS {{ CODE: load = ROM; }}"
+            ),
+            "out.bin",
+        )
+        .unwrap();
+        let mut obj = TestObject::new("main");
+        let sect_i = obj.section("CODE");
+        obj.literal(sect_i, &[0xEA; 0x100]);
+        let bytes = obj.encode();
+        let objs = [parse_object("main", &bytes)];
+
+        let result = link(&script, &objs);
+        assert_eq!(result.segment("CODE").unwrap().start(), 0xFF80);
+    }
+
+    #[test]
+    fn bank_cross_is_allowed_by_default() {
+        link_across_bank_boundary("");
+    }
+
+    #[test]
+    #[should_panic(expected = "crosses a bank boundary at $010000")]
+    fn bank_cross_is_rejected_with_bankcross_no() {
+        link_across_bank_boundary(", bankcross = no");
+    }
+
+    #[test]
+    #[should_panic(expected = "segment 'BSS' overflows memory 'RAM' by $0080 bytes")]
+    fn bss_overflow_is_rejected() {
+        let script = LinkScript::load(
+            r#"
+MEMORY {
+    RAM: start = $0200, size = $100, file = "";
+}
+SEGMENTS {
+    BSS: load = RAM, type = bss;
+}
+"#,
+            "out.bin",
+        )
+        .unwrap();
+        let mut obj = TestObject::new("main");
+        let sect_i = obj.section("BSS");
+        obj.fill(sect_i, 0x180);
+        let bytes = obj.encode();
+        let objs = [parse_object("main", &bytes)];
+
+        link(&script, &objs);
+    }
 }
@@ -0,0 +1,281 @@
+//! マップファイル、ラベルファイルの生成。
+
+use std::fmt::Write as _;
+
+use crate::index::{ExportIdx, ObjIdx, ObjImportIdx};
+use crate::object::{Object, ADDR_SIZE_ABS, ADDR_SIZE_FAR, ADDR_SIZE_LONG, ADDR_SIZE_ZP};
+
+use super::budget::{SizeBudget, SizeBudgetKind};
+use super::graph::LinkGraph;
+use super::layout::LinkLayout;
+use super::symbol::{ExportEntry, SymbolTable};
+
+/// リンカスクリプト/コマンドラインで定義されたシンボルの定義元として表示する名前。
+const LINKER_GENERATED: &str = "[linker generated]";
+
+/// ld65 互換のマップファイルの内容を返す。
+pub fn write_map(
+    objs: &[Object],
+    graph: &LinkGraph,
+    layout: &LinkLayout,
+    sym_table: &SymbolTable,
+    size_budgets: &[SizeBudget],
+) -> String {
+    let mut out = String::new();
+
+    write_modules(&mut out, objs, graph, layout);
+    out.push('\n');
+    write_segments(&mut out, objs, graph, layout);
+    out.push_str("\n\n");
+    if graph.packed_segs().len() > 0 {
+        write_packed_segments(&mut out, graph, layout);
+        out.push_str("\n\n");
+    }
+    if graph
+        .sects()
+        .any(|sect_i| layout.section(sect_i).page_pad() > 0)
+    {
+        write_page_pads(&mut out, objs, graph, layout);
+        out.push_str("\n\n");
+    }
+    if !size_budgets.is_empty() {
+        write_size_budgets(&mut out, size_budgets);
+        out.push_str("\n\n");
+    }
+    write_exports(&mut out, sym_table, "Exports list by name:", |x, y| {
+        x.name().cmp(y.name())
+    });
+    out.push_str("\n\n");
+    write_exports(&mut out, sym_table, "Exports list by value:", |x, y| {
+        x.value()
+            .cmp(&y.value())
+            .then_with(|| x.name().cmp(y.name()))
+    });
+    out.push_str("\n\n");
+    write_imports(&mut out, objs, sym_table);
+
+    out
+}
+
+/// VICE 形式のラベルファイルの内容を返す。
+pub fn write_labels(sym_table: &SymbolTable) -> String {
+    let mut exports: Vec<_> = sym_table.enumerate_exports().map(|(_, x)| x).collect();
+    exports.sort_by(|x, y| {
+        x.value()
+            .cmp(&y.value())
+            .then_with(|| x.name().cmp(y.name()))
+    });
+
+    let mut out = String::new();
+
+    for export in exports {
+        writeln!(out, "al {:06X} .{}", export.value(), export.name()).unwrap();
+    }
+
+    out
+}
+
+fn write_modules(out: &mut String, objs: &[Object], graph: &LinkGraph, layout: &LinkLayout) {
+    out.push_str("Modules list:\n");
+    out.push_str("-------------\n");
+
+    for (obj_i, obj) in objs.iter().enumerate() {
+        writeln!(out, "{}:", obj.name()).unwrap();
+
+        for sect_i in graph.obj_to_sects(ObjIdx::new(obj_i)) {
+            let (_, obj_sect_i) = graph.sect_to_obj_sect(sect_i);
+            let obj_sect = obj.section(obj_sect_i);
+            let seg_i = graph.sect_to_seg(sect_i);
+            let layout_sect = layout.section(sect_i);
+            let off = layout_sect.start() - layout.segment(seg_i).start();
+            // Fill はセクション直前に挿入されたパディングのサイズ。
+            let fill = layout_sect.page_pad();
+            writeln!(
+                out,
+                "    {:<17} Offs={off:06X}  Size={:06X}  Align={:05X}  Fill={fill:04X}",
+                graph.seg_name(seg_i),
+                obj_sect.len(),
+                obj_sect.align(),
+            )
+            .unwrap();
+        }
+    }
+}
+
+fn write_segments(out: &mut String, objs: &[Object], graph: &LinkGraph, layout: &LinkLayout) {
+    out.push_str("Segment list:\n");
+    out.push_str("-------------\n");
+    out.push_str("Name                   Start     End    Size  Align\n");
+    out.push_str("----------------------------------------------------\n");
+
+    let mut seg_is: Vec<_> = graph
+        .segs()
+        .filter(|&seg_i| layout.segment(seg_i).len() > 0)
+        .collect();
+    seg_is.sort_by_key(|&seg_i| layout.segment(seg_i).start());
+
+    for seg_i in seg_is {
+        let layout_seg = layout.segment(seg_i);
+        let start = layout_seg.start();
+        let len = layout_seg.len();
+        // セグメントのアラインメントは、含まれるセクションのアラインメントの最大値。
+        let align = graph
+            .seg_to_sects(seg_i)
+            .map(|sect_i| {
+                let (obj_i, obj_sect_i) = graph.sect_to_obj_sect(sect_i);
+                objs[obj_i.get()].section(obj_sect_i).align()
+            })
+            .max()
+            .unwrap_or(1);
+        writeln!(
+            out,
+            "{:<21} {start:06X}  {:06X}  {len:06X}  {align:05X}",
+            graph.seg_name(seg_i),
+            start + len - 1,
+        )
+        .unwrap();
+    }
+}
+
+/// 自動配置されたセグメントの配置先を出力する (オリジナルにはない項目)。
+fn write_packed_segments(out: &mut String, graph: &LinkGraph, layout: &LinkLayout) {
+    out.push_str("Segment placement:\n");
+    out.push_str("------------------\n");
+    out.push_str("Name                  Memory                  Size\n");
+    out.push_str("--------------------------------------------------\n");
+
+    for seg_i in graph.packed_segs() {
+        writeln!(
+            out,
+            "{:<21} {:<21} {:06X}",
+            graph.seg_name(seg_i),
+            graph.mem_name(graph.seg_to_mem(seg_i)),
+            layout.segment(seg_i).len(),
+        )
+        .unwrap();
+    }
+}
+
+/// ページ境界をまたがないよう移されたセクションと、そのパディングを出力する (オリジナルにはない項目)。
+fn write_page_pads(out: &mut String, objs: &[Object], graph: &LinkGraph, layout: &LinkLayout) {
+    out.push_str("Page-crossing padding:\n");
+    out.push_str("----------------------\n");
+    out.push_str("Name                  Module                 Start     Pad\n");
+    out.push_str("----------------------------------------------------------\n");
+
+    let mut total = 0;
+    for sect_i in graph.sects() {
+        let layout_sect = layout.section(sect_i);
+        let pad = layout_sect.page_pad();
+        if pad == 0 {
+            continue;
+        }
+        let (obj_i, _) = graph.sect_to_obj_sect(sect_i);
+        writeln!(
+            out,
+            "{:<21} {:<21} {:06X}  {pad:06X}",
+            graph.seg_name(graph.sect_to_seg(sect_i)),
+            objs[obj_i.get()].name(),
+            layout_sect.start(),
+        )
+        .unwrap();
+        total += pad;
+    }
+    writeln!(out, "{:<21} {:<21} {:6}  {total:06X}", "Total", "", "").unwrap();
+}
+
+/// サイズの上限/期待値と実際のサイズを出力する (オリジナルにはない項目)。
+fn write_size_budgets(out: &mut String, size_budgets: &[SizeBudget]) {
+    out.push_str("Size budgets:\n");
+    out.push_str("-------------\n");
+    out.push_str("Name                  Kind        Used  Budget   Remain\n");
+    out.push_str("-------------------------------------------------------\n");
+
+    for budget in size_budgets {
+        let kind = match budget.kind() {
+            SizeBudgetKind::Segment => "segment",
+            SizeBudgetKind::File => "file",
+        };
+        writeln!(
+            out,
+            "{:<21} {kind:<7}  {:06X}  {:06X}  {:+7}",
+            budget.name(),
+            budget.used(),
+            budget.budget(),
+            budget.budget() as i64 - budget.used() as i64,
+        )
+        .unwrap();
+    }
+}
+
+fn write_exports<F>(out: &mut String, sym_table: &SymbolTable, title: &str, cmp: F)
+where
+    F: FnMut(&&ExportEntry, &&ExportEntry) -> std::cmp::Ordering,
+{
+    writeln!(out, "{title}").unwrap();
+    writeln!(out, "{}", "-".repeat(title.len())).unwrap();
+
+    let mut exports: Vec<_> = sym_table.enumerate_exports().map(|(_, x)| x).collect();
+    exports.sort_by(cmp);
+
+    // ld65 と同様、1 行に 2 シンボルずつ出力する。
+    for chunk in exports.chunks(2) {
+        let line: Vec<_> = chunk
+            .iter()
+            .map(|export| {
+                format!(
+                    "{:<25} {:06X} {}{}{}",
+                    export.name(),
+                    export.value(),
+                    if export.is_referenced() { 'R' } else { ' ' },
+                    if export.is_label() { 'L' } else { 'E' },
+                    addr_size_char(export.addr_size()),
+                )
+            })
+            .collect();
+        writeln!(out, "{}", line.join("    ")).unwrap();
+    }
+}
+
+fn write_imports(out: &mut String, objs: &[Object], sym_table: &SymbolTable) {
+    out.push_str("Imports list:\n");
+    out.push_str("-------------\n");
+
+    // エクスポートシンボルごとに、それをインポートしているオブジェクトファイルを集める。
+    let mut importers = vec![Vec::<&str>::new(); sym_table.export_count()];
+    for (obj_i, obj) in objs.iter().enumerate() {
+        let obj_i = ObjIdx::new(obj_i);
+        for imp_i in 0..obj.xo65().import_table().count() {
+            let imp_i = ObjImportIdx::new(imp_i);
+            let export_i = sym_table.get(obj_i, imp_i).export_idx();
+            importers[export_i.get()].push(obj.name());
+        }
+    }
+
+    let mut export_is: Vec<_> = (0..sym_table.export_count())
+        .map(ExportIdx::new)
+        .filter(|export_i| !importers[export_i.get()].is_empty())
+        .collect();
+    export_is.sort_by(|&x, &y| sym_table.export(x).name().cmp(sym_table.export(y).name()));
+
+    for export_i in export_is {
+        let export = sym_table.export(export_i);
+        let def = export
+            .object_idx()
+            .map_or(LINKER_GENERATED, |obj_i| objs[obj_i.get()].name());
+        writeln!(out, "{} ({def}):", export.name()).unwrap();
+        for importer in &importers[export_i.get()] {
+            writeln!(out, "    {importer}").unwrap();
+        }
+    }
+}
+
+fn addr_size_char(addr_size: u8) -> char {
+    match addr_size {
+        ADDR_SIZE_ZP => 'Z',
+        ADDR_SIZE_ABS => 'A',
+        ADDR_SIZE_FAR => 'F',
+        ADDR_SIZE_LONG => 'L',
+        _ => ' ',
+    }
+}
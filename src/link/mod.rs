@@ -1,21 +1,48 @@
 use crate::object::Object;
 use crate::script::LinkScript;
 
+mod budget;
+mod crossbank;
 mod emit;
 mod graph;
 mod layout;
+mod map;
+mod report;
+mod result;
+mod stats;
 mod symbol;
 
+pub use self::budget::{SizeBudget, SizeBudgetKind};
+pub use self::crossbank::CrossBankRef;
+pub use self::result::{LinkExport, LinkMemory, LinkResult, LinkSection, LinkSegment};
+pub use self::stats::{LinkStats, MemoryStats, SegmentStats};
+
 use self::graph::LinkGraph;
 use self::layout::LinkLayout;
 use self::symbol::SymbolTable;
 
-pub fn link(script: &LinkScript, objs: &[Object]) -> LinkOutputs {
+pub fn link<'objs, 'data>(
+    script: &LinkScript,
+    objs: &'objs [Object<'data>],
+) -> LinkResult<'objs, 'data> {
+    check_script(script);
+
+    let script = &expand_segment_patterns(script, objs);
+
     let graph = LinkGraph::new(script, objs);
 
     let layout = LinkLayout::new(script, objs, &graph);
 
-    let sym_table = SymbolTable::new(objs, &graph, &layout);
+    let size_budgets = self::budget::collect_size_budgets(script, &graph, &layout);
+    self::budget::check_size_budgets(&size_budgets);
+
+    let sym_table = SymbolTable::new(script, objs, &graph, &layout);
+
+    let cross_bank_refs =
+        self::crossbank::find_cross_bank_refs(script, objs, &graph, &layout, &sym_table);
+    for cross_bank_ref in &cross_bank_refs {
+        eprintln!("warning: {cross_bank_ref}");
+    }
 
     let mut outputs = Vec::<LinkOutput>::with_capacity(graph.file_count());
 
@@ -28,22 +55,40 @@ pub fn link(script: &LinkScript, objs: &[Object]) -> LinkOutputs {
         outputs.push(output);
     }
 
-    LinkOutputs {
-        outputs: outputs.into(),
-    }
+    LinkResult::new(
+        objs,
+        graph,
+        layout,
+        sym_table,
+        cross_bank_refs,
+        size_budgets,
+        outputs.into(),
+    )
 }
 
-#[derive(Debug)]
-pub struct LinkOutputs {
-    outputs: Box<[LinkOutput]>,
+/// リンカスクリプトを静的に検査する。警告は出力し、エラーがあれば panic する。
+fn check_script(script: &LinkScript) {
+    for issue in script.check() {
+        assert!(!issue.is_error(), "{}", issue.message());
+        eprintln!("{issue}");
+    }
 }
 
-impl LinkOutputs {
-    pub fn iter(
-        &self,
-    ) -> impl ExactSizeIterator<Item = &LinkOutput> + std::iter::FusedIterator + Clone {
-        self.outputs.iter()
+/// リンカスクリプト内のセグメント名のパターンを、オブジェクトファイル内のセグメント名に展開する。
+fn expand_segment_patterns(script: &LinkScript, objs: &[Object]) -> LinkScript {
+    let seg_names = objs.iter().flat_map(|obj| {
+        obj.enumerate_sections()
+            .map(|(obj_sect_i, obj_sect)| (obj.query_segment_name(obj_sect_i), obj_sect.is_empty()))
+            .filter(|&(seg_name, is_empty)| LinkGraph::needs_segment(seg_name, is_empty))
+            .map(|(seg_name, _)| seg_name)
+    });
+
+    let (script, issues) = script.expand_segment_patterns(seg_names);
+    for issue in issues {
+        eprintln!("{issue}");
     }
+
+    script
 }
 
 #[derive(Debug)]
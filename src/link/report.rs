@@ -0,0 +1,191 @@
+//! JSON 形式のリンクレポートの生成。
+//!
+//! スキーマは `LinkResult::report` を参照。
+
+use std::fmt::Write as _;
+
+use crate::object::{Object, ADDR_SIZE_ABS, ADDR_SIZE_FAR, ADDR_SIZE_LONG, ADDR_SIZE_ZP};
+
+use super::graph::LinkGraph;
+use super::layout::LinkLayout;
+use super::symbol::SymbolTable;
+use super::LinkOutput;
+
+/// レポートのスキーマのバージョン。
+const REPORT_VERSION: u32 = 1;
+
+/// JSON 形式のリンクレポートを返す。
+pub fn write_report(
+    objs: &[Object],
+    graph: &LinkGraph,
+    layout: &LinkLayout,
+    sym_table: &SymbolTable,
+    outputs: &[LinkOutput],
+) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "{{").unwrap();
+    writeln!(out, "  \"version\": {REPORT_VERSION},").unwrap();
+
+    let stats = super::stats::collect_stats(graph, layout);
+    let mems = graph.mems().zip(stats.iter_memorys()).map(|(mem_i, mem)| {
+        let layout_mem = layout.memory(mem_i);
+        let gap = match mem.largest_free_gap() {
+            Some(gap) => format!("{{ \"start\": {}, \"size\": {} }}", gap.min(), gap.len()),
+            None => "null".to_owned(),
+        };
+        let file = graph
+            .mem_to_file(mem_i)
+            .map(|file_i| graph.file_name(file_i));
+        format!(
+            "{{ \"name\": {}, \"start\": {}, \"size\": {}, \"used\": {}, \"free\": {}, \"largest_free_gap\": {gap}, \"file\": {}, \"file_offset\": {}, \"output_len\": {} }}",
+            json_str(mem.name()),
+            mem.start(),
+            mem.size(),
+            mem.used(),
+            mem.free(),
+            json_opt_str(file),
+            layout_mem.file_offset(),
+            layout_mem.output_len()
+        )
+    });
+    write_array(&mut out, "memories", mems, false);
+
+    let segs = graph
+        .mems()
+        .flat_map(|mem_i| graph.mem_to_segs(mem_i))
+        .map(|seg_i| {
+            let layout_seg = layout.segment(seg_i);
+            format!(
+                "{{ \"name\": {}, \"memory\": {}, \"start\": {}, \"size\": {}, \"output_len\": {} }}",
+                json_str(graph.seg_name(seg_i)),
+                json_str(graph.mem_name(graph.seg_to_mem(seg_i))),
+                layout_seg.start(),
+                layout_seg.len(),
+                layout_seg.output_len()
+            )
+        });
+    write_array(&mut out, "segments", segs, false);
+
+    let sects = graph.sects().map(|sect_i| {
+        let (obj_i, obj_sect_i) = graph.sect_to_obj_sect(sect_i);
+        let obj = &objs[obj_i.get()];
+        let layout_sect = layout.section(sect_i);
+        format!(
+            "{{ \"object\": {}, \"segment\": {}, \"start\": {}, \"size\": {}, \"page_pad\": {} }}",
+            json_str(obj.name()),
+            json_str(graph.seg_name(graph.sect_to_seg(sect_i))),
+            layout_sect.start(),
+            obj.section(obj_sect_i).len(),
+            layout_sect.page_pad()
+        )
+    });
+    write_array(&mut out, "sections", sects, false);
+
+    let mut exports: Vec<_> = sym_table.enumerate_exports().map(|(_, x)| x).collect();
+    exports.sort_by(|x, y| x.name().cmp(y.name()));
+    let exports = exports.into_iter().map(|export| {
+        let object = export.object_idx().map(|obj_i| objs[obj_i.get()].name());
+        format!(
+            "{{ \"name\": {}, \"value\": {}, \"addr_size\": {}, \"label\": {}, \"referenced\": {}, \"object\": {} }}",
+            json_str(export.name()),
+            export.value(),
+            json_opt_str(addr_size_name(export.addr_size())),
+            export.is_label(),
+            export.is_referenced(),
+            json_opt_str(object)
+        )
+    });
+    write_array(&mut out, "exports", exports, false);
+
+    let files = outputs.iter().map(|output| {
+        format!(
+            "{{ \"name\": {}, \"size\": {} }}",
+            json_str(output.path()),
+            output.body().len()
+        )
+    });
+    write_array(&mut out, "files", files, true);
+
+    writeln!(out, "}}").unwrap();
+
+    out
+}
+
+/// `"key": [...]` を 1 要素 1 行で出力する。
+fn write_array(out: &mut String, key: &str, elems: impl Iterator<Item = String>, last: bool) {
+    let elems: Vec<_> = elems.collect();
+
+    if elems.is_empty() {
+        write!(out, "  \"{key}\": []").unwrap();
+    } else {
+        writeln!(out, "  \"{key}\": [").unwrap();
+        for (i, elem) in elems.iter().enumerate() {
+            let sep = if i + 1 < elems.len() { "," } else { "" };
+            writeln!(out, "    {elem}{sep}").unwrap();
+        }
+        write!(out, "  ]").unwrap();
+    }
+
+    out.push_str(if last { "\n" } else { ",\n" });
+}
+
+fn json_opt_str(s: Option<&str>) -> String {
+    s.map_or_else(|| "null".to_owned(), json_str)
+}
+
+/// 文字列を JSON の文字列リテラルにする。
+fn json_str(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if c.is_control() => write!(res, "\\u{:04x}", c as u32).unwrap(),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+
+    res
+}
+
+/// アドレスサイズ名を返す。不明な値の場合は `None`。
+fn addr_size_name(addr_size: u8) -> Option<&'static str> {
+    let name = match addr_size {
+        ADDR_SIZE_ZP => "zp",
+        ADDR_SIZE_ABS => "abs",
+        ADDR_SIZE_FAR => "far",
+        ADDR_SIZE_LONG => "long",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_str_escapes() {
+        assert_eq!(json_str(""), r#""""#);
+        assert_eq!(json_str("main.o"), r#""main.o""#);
+        assert_eq!(json_str(r#"a"b\c"#), r#""a\"b\\c""#);
+        assert_eq!(json_str("C:\\cc65\\lib"), r#""C:\\cc65\\lib""#);
+        assert_eq!(json_str("a\nb\r\tc"), r#""a\nb\r\tc""#);
+        assert_eq!(json_str("\u{0}\u{1b}\u{7f}"), r#""\u0000\u001b\u007f""#);
+        assert_eq!(json_str("ラベル"), r#""ラベル""#);
+    }
+
+    #[test]
+    fn json_opt_str_null() {
+        assert_eq!(json_opt_str(None), "null");
+        assert_eq!(json_opt_str(Some("x")), r#""x""#);
+    }
+}
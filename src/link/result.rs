@@ -0,0 +1,457 @@
+//! リンク結果。
+
+use std::collections::HashMap;
+
+use crate::index::{ExportIdx, MemIdx, ObjIdx, SectIdx, SegIdx};
+use crate::object::Object;
+use crate::range::NonemptyRange;
+
+use super::budget::SizeBudget;
+use super::crossbank::CrossBankRef;
+use super::graph::LinkGraph;
+use super::layout::LinkLayout;
+use super::stats::LinkStats;
+use super::symbol::SymbolTable;
+use super::LinkOutput;
+
+/// リンク結果。
+///
+/// 出力ファイルの内容に加え、リンク時に決定したシンボルの値や各要素の配置を名前で問い合わせられる。
+#[derive(Debug)]
+pub struct LinkResult<'objs, 'data> {
+    objs: &'objs [Object<'data>],
+    graph: LinkGraph,
+    layout: LinkLayout,
+    sym_table: SymbolTable,
+    cross_bank_refs: Box<[CrossBankRef]>,
+    size_budgets: Box<[SizeBudget]>,
+    outputs: Box<[LinkOutput]>,
+    export_name_to_idx: HashMap<String, ExportIdx>,
+}
+
+impl<'objs, 'data> LinkResult<'objs, 'data> {
+    pub(super) fn new(
+        objs: &'objs [Object<'data>],
+        graph: LinkGraph,
+        layout: LinkLayout,
+        sym_table: SymbolTable,
+        cross_bank_refs: Box<[CrossBankRef]>,
+        size_budgets: Box<[SizeBudget]>,
+        outputs: Box<[LinkOutput]>,
+    ) -> Self {
+        let export_name_to_idx = sym_table
+            .enumerate_exports()
+            .map(|(export_i, export)| (export.name().to_owned(), export_i))
+            .collect();
+
+        Self {
+            objs,
+            graph,
+            layout,
+            sym_table,
+            cross_bank_refs,
+            size_budgets,
+            outputs,
+            export_name_to_idx,
+        }
+    }
+
+    /// 出力ファイルを列挙する。
+    pub fn outputs(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &LinkOutput> + std::iter::FusedIterator + Clone {
+        self.outputs.iter()
+    }
+
+    /// 切り替え可能なバンクから別の切り替え可能なバンクへの参照を列挙する
+    /// (メモリ領域の fixed 属性を参照)。
+    pub fn cross_bank_refs(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &CrossBankRef> + std::iter::FusedIterator + Clone {
+        self.cross_bank_refs.iter()
+    }
+
+    /// セグメントのサイズの上限 (maxsize 属性) と出力ファイルの期待されるサイズ (FILES ブロックの size 属性)
+    /// を、実際のサイズとともに列挙する。
+    pub fn size_budgets(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &SizeBudget> + std::iter::FusedIterator + Clone {
+        self.size_budgets.iter()
+    }
+
+    /// メモリ領域ごとの使用状況 (使用/空きサイズ、最大の空き領域) とセグメントごとのサイズを集計する。
+    ///
+    /// 結果は `Display` で表形式に整形できる (`--stats` の出力)。
+    pub fn stats(&self) -> LinkStats<'_> {
+        super::stats::collect_stats(&self.graph, &self.layout)
+    }
+
+    /// ld65 互換のマップファイルの内容を生成する。
+    pub fn map(&self) -> String {
+        super::map::write_map(
+            self.objs,
+            &self.graph,
+            &self.layout,
+            &self.sym_table,
+            &self.size_budgets,
+        )
+    }
+
+    /// JSON 形式のリンクレポートを生成する。
+    ///
+    /// # スキーマ (version 1)
+    ///
+    /// 数値は全て 10 進の整数で、アドレスおよびサイズはバイト単位。
+    /// 配列の順序は、特記ない限りリンク時の内部順序 (リンカスクリプト内の順など) とする。
+    /// 互換性のない変更を行う場合は `version` を上げる (フィールドの追加のみなら上げない)。
+    ///
+    /// ```text
+    /// {
+    ///   "version": 1,
+    ///   "memories": [
+    ///     {
+    ///       "name": string,
+    ///       "start": int,
+    ///       "size": int,
+    ///       "used": int,              // 先頭から最も後ろにあるセグメントの終端まで (LinkMemory::used() と同じ)
+    ///       "free": int,              // size - used (末尾の空き。途中の隙間は largest_free_gap を参照)
+    ///       "largest_free_gap": { "start": int, "size": int } | null,
+    ///       "file": string | null,    // 出力先ファイル名
+    ///       "file_offset": int,
+    ///       "output_len": int         // 実際にファイルへ出力されるサイズ
+    ///     }, ...
+    ///   ],
+    ///   "segments": [                 // メモリ領域内の配置順。分割配置/複製されたセグメントは部分ごと
+    ///     {
+    ///       "name": string,
+    ///       "memory": string,
+    ///       "start": int,
+    ///       "size": int,              // BSS を含む
+    ///       "output_len": int
+    ///     }, ...
+    ///   ],
+    ///   "sections": [                 // オブジェクトファイル順、オブジェクトファイル内の順
+    ///     {
+    ///       "object": string,
+    ///       "segment": string,
+    ///       "start": int,
+    ///       "size": int,
+    ///       "page_pad": int           // 直前に挿入されたページ境界のパディング
+    ///     }, ...
+    ///   ],
+    ///   "exports": [                  // 名前順
+    ///     {
+    ///       "name": string,
+    ///       "value": int,
+    ///       "addr_size": "zp" | "abs" | "far" | "long" | null,
+    ///       "label": bool,
+    ///       "referenced": bool,
+    ///       "object": string | null   // リンカスクリプト/コマンドラインで定義された場合は null
+    ///     }, ...
+    ///   ],
+    ///   "files": [
+    ///     { "name": string, "size": int }, ...
+    ///   ]
+    /// }
+    /// ```
+    pub fn report(&self) -> String {
+        super::report::write_report(
+            self.objs,
+            &self.graph,
+            &self.layout,
+            &self.sym_table,
+            &self.outputs,
+        )
+    }
+
+    /// VICE 形式のラベルファイルの内容を生成する。
+    pub fn labels(&self) -> String {
+        super::map::write_labels(&self.sym_table)
+    }
+
+    /// 名前からエクスポートシンボルを得る。
+    pub fn export(&self, name: &str) -> Option<LinkExport<'_>> {
+        self.export_name_to_idx
+            .get(name)
+            .map(|&export_i| self.make_export(export_i))
+    }
+
+    pub fn iter_exports(
+        &self,
+    ) -> impl ExactSizeIterator<Item = LinkExport<'_>> + std::iter::FusedIterator + Clone {
+        (0..self.sym_table.export_count()).map(|i| self.make_export(ExportIdx::new(i)))
+    }
+
+    /// 名前からメモリ領域を得る。
+    pub fn memory(&self, name: &str) -> Option<LinkMemory<'_>> {
+        self.graph
+            .mems()
+            .find(|&mem_i| self.graph.mem_name(mem_i) == name)
+            .map(|mem_i| self.make_memory(mem_i))
+    }
+
+    pub fn iter_memorys(
+        &self,
+    ) -> impl ExactSizeIterator<Item = LinkMemory<'_>> + std::iter::FusedIterator + Clone {
+        self.graph.mems().map(|mem_i| self.make_memory(mem_i))
+    }
+
+    /// 名前からセグメントを得る。
+    pub fn segment(&self, name: &str) -> Option<LinkSegment<'_>> {
+        self.graph
+            .segs()
+            .find(|&seg_i| self.graph.seg_name(seg_i) == name)
+            .map(|seg_i| self.make_segment(seg_i))
+    }
+
+    pub fn iter_segments(
+        &self,
+    ) -> impl ExactSizeIterator<Item = LinkSegment<'_>> + std::iter::FusedIterator + Clone {
+        self.graph.segs().map(|seg_i| self.make_segment(seg_i))
+    }
+
+    /// オブジェクトファイル名から、そのオブジェクトファイル内の (リンクされた) セクションを列挙する。
+    ///
+    /// 同名のオブジェクトファイルが複数ある場合、最初のものを対象とする。
+    pub fn object_sections(
+        &self,
+        obj_name: &str,
+    ) -> Option<impl ExactSizeIterator<Item = LinkSection<'_>> + std::iter::FusedIterator + Clone>
+    {
+        let obj_i = self.objs.iter().position(|obj| obj.name() == obj_name)?;
+        let sects = self
+            .graph
+            .obj_to_sects(ObjIdx::new(obj_i))
+            .map(|sect_i| self.make_section(sect_i));
+
+        Some(sects)
+    }
+
+    fn make_export(&self, export_i: ExportIdx) -> LinkExport<'_> {
+        let export = self.sym_table.export(export_i);
+
+        LinkExport {
+            name: export.name(),
+            value: export.value(),
+            addr_size: export.addr_size(),
+            label: export.is_label(),
+            referenced: export.is_referenced(),
+            object: export
+                .object_idx()
+                .map(|obj_i| self.objs[obj_i.get()].name()),
+        }
+    }
+
+    fn make_memory(&self, mem_i: MemIdx) -> LinkMemory<'_> {
+        let layout_mem = self.layout.memory(mem_i);
+
+        LinkMemory {
+            name: self.graph.mem_name(mem_i),
+            file: self
+                .graph
+                .mem_to_file(mem_i)
+                .map(|file_i| self.graph.file_name(file_i)),
+            range: layout_mem.range(),
+            used: layout_mem.used(),
+            file_off: layout_mem.file_offset(),
+            output_len: layout_mem.output_len(),
+        }
+    }
+
+    fn make_segment(&self, seg_i: SegIdx) -> LinkSegment<'_> {
+        let layout_seg = self.layout.segment(seg_i);
+
+        LinkSegment {
+            name: self.graph.seg_name(seg_i),
+            memory: self.graph.mem_name(self.graph.seg_to_mem(seg_i)),
+            start: layout_seg.start(),
+            len: layout_seg.len(),
+            output_len: layout_seg.output_len(),
+        }
+    }
+
+    fn make_section(&self, sect_i: SectIdx) -> LinkSection<'_> {
+        let (obj_i, obj_sect_i) = self.graph.sect_to_obj_sect(sect_i);
+        let obj = &self.objs[obj_i.get()];
+
+        LinkSection {
+            object: obj.name(),
+            segment: self.graph.seg_name(self.graph.sect_to_seg(sect_i)),
+            start: self.layout.section(sect_i).start(),
+            len: obj.section(obj_sect_i).len() as usize,
+            page_pad: self.layout.section(sect_i).page_pad(),
+        }
+    }
+}
+
+/// リンク結果におけるエクスポートシンボル。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LinkExport<'a> {
+    name: &'a str,
+    value: i64,
+    addr_size: u8,
+    label: bool,
+    referenced: bool,
+    object: Option<&'a str>,
+}
+
+impl<'a> LinkExport<'a> {
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn value(&self) -> i64 {
+        self.value
+    }
+
+    pub fn addr_size(&self) -> u8 {
+        self.addr_size
+    }
+
+    /// ラベル (アドレス) として定義されたかどうかを返す。
+    pub fn is_label(&self) -> bool {
+        self.label
+    }
+
+    /// いずれかのオブジェクトファイルからインポートされているかどうかを返す。
+    pub fn is_referenced(&self) -> bool {
+        self.referenced
+    }
+
+    /// 定義元のオブジェクトファイル名を返す。リンカスクリプトやコマンドラインで定義された場合は `None`。
+    pub fn object_name(&self) -> Option<&'a str> {
+        self.object
+    }
+}
+
+/// リンク結果におけるメモリ領域。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LinkMemory<'a> {
+    name: &'a str,
+    file: Option<&'a str>,
+    range: NonemptyRange,
+    used: usize,
+    file_off: usize,
+    output_len: usize,
+}
+
+impl<'a> LinkMemory<'a> {
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// 出力先ファイル名を返す。どのファイルにも出力されない場合は `None`。
+    pub fn file_name(&self) -> Option<&'a str> {
+        self.file
+    }
+
+    /// アドレス範囲 (リンカスクリプトで指定されたもの) を返す。
+    pub fn range(&self) -> NonemptyRange {
+        self.range
+    }
+
+    pub fn start(&self) -> usize {
+        self.range.min()
+    }
+
+    pub fn size(&self) -> usize {
+        self.range.len()
+    }
+
+    /// 先頭から、最も後ろにあるセグメントの終端までのサイズを返す (BSS も含む)。
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// 出力ファイル内オフセットを返す。
+    pub fn file_offset(&self) -> usize {
+        self.file_off
+    }
+
+    /// 実際にファイルへ出力されるサイズを返す。
+    pub fn output_len(&self) -> usize {
+        self.output_len
+    }
+}
+
+/// リンク結果におけるセグメント。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LinkSegment<'a> {
+    name: &'a str,
+    memory: &'a str,
+    start: usize,
+    len: usize,
+    output_len: usize,
+}
+
+impl<'a> LinkSegment<'a> {
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// ロード先メモリ領域名を返す。
+    pub fn memory_name(&self) -> &'a str {
+        self.memory
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// アドレス空間内に占めるサイズを返す (BSS も含む)。
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// アドレス範囲を返す。サイズ 0 の場合は `None`。
+    pub fn range(&self) -> Option<NonemptyRange> {
+        (self.len > 0).then(|| NonemptyRange::from_start_len(self.start, self.len))
+    }
+
+    /// 実際にファイルへ出力されるサイズを返す。
+    pub fn output_len(&self) -> usize {
+        self.output_len
+    }
+}
+
+/// リンク結果におけるセクション (オブジェクトファイル内のセグメントの断片)。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LinkSection<'a> {
+    object: &'a str,
+    segment: &'a str,
+    start: usize,
+    len: usize,
+    page_pad: usize,
+}
+
+impl<'a> LinkSection<'a> {
+    /// 所属するオブジェクトファイル名を返す。
+    pub fn object_name(&self) -> &'a str {
+        self.object
+    }
+
+    /// 所属するセグメント名を返す。
+    pub fn segment_name(&self) -> &'a str {
+        self.segment
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// アドレス範囲を返す。サイズ 0 の場合は `None`。
+    pub fn range(&self) -> Option<NonemptyRange> {
+        (self.len > 0).then(|| NonemptyRange::from_start_len(self.start, self.len))
+    }
+
+    /// ページ境界をまたがないよう、直前に挿入されたパディングのサイズを返す (pagecross 属性)。
+    pub fn page_pad(&self) -> usize {
+        self.page_pad
+    }
+}
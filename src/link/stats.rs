@@ -0,0 +1,269 @@
+//! メモリ領域とセグメントの使用状況の集計。
+
+use crate::index::MemIdx;
+use crate::range::NonemptyRange;
+
+use super::graph::LinkGraph;
+use super::layout::LinkLayout;
+
+/// メモリ領域ごとの使用状況と、セグメントごとのサイズを集計する。
+pub fn collect_stats<'a>(graph: &'a LinkGraph, layout: &LinkLayout) -> LinkStats<'a> {
+    let mems: Box<[_]> = graph
+        .mems()
+        .map(|mem_i| collect_memory_stats(graph, layout, mem_i))
+        .collect();
+
+    let segs: Box<[_]> = graph
+        .mems()
+        .flat_map(|mem_i| graph.mem_to_segs(mem_i))
+        .map(|seg_i| {
+            let layout_seg = layout.segment(seg_i);
+            SegmentStats {
+                name: graph.seg_name(seg_i),
+                memory: graph.mem_name(graph.seg_to_mem(seg_i)),
+                start: layout_seg.start(),
+                size: layout_seg.len(),
+            }
+        })
+        .collect();
+
+    LinkStats { mems, segs }
+}
+
+fn collect_memory_stats<'a>(
+    graph: &'a LinkGraph,
+    layout: &LinkLayout,
+    mem_i: MemIdx,
+) -> MemoryStats<'a> {
+    let range = layout.memory(mem_i).range();
+
+    // セグメントが占めるアドレス範囲 (BSS も含む)。
+    let mut seg_ranges: Vec<_> = graph
+        .mem_to_segs(mem_i)
+        .map(|seg_i| layout.segment(seg_i))
+        .filter(|layout_seg| layout_seg.len() > 0)
+        .map(|layout_seg| NonemptyRange::from_start_len(layout_seg.start(), layout_seg.len()))
+        .collect();
+    seg_ranges.sort_by_key(|r| r.min());
+
+    // セグメント間およびメモリ領域末尾の空きのうち、最大のもの (同じ大きさなら先頭側)。
+    let mut largest_gap = None::<NonemptyRange>;
+    let mut addr = range.min();
+    let ends = seg_ranges
+        .iter()
+        .map(|r| (r.min(), r.max() + 1))
+        .chain(std::iter::once((range.max() + 1, range.max() + 1)));
+    for (start, end) in ends {
+        if addr < start {
+            let gap = NonemptyRange::from_min_max(addr, start - 1);
+            if largest_gap.is_none_or(|largest| largest.len() < gap.len()) {
+                largest_gap = Some(gap);
+            }
+        }
+        addr = addr.max(end);
+    }
+
+    MemoryStats {
+        name: graph.mem_name(mem_i),
+        range,
+        used: layout.memory(mem_i).used(),
+        largest_gap,
+    }
+}
+
+/// リンク結果の使用状況。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LinkStats<'a> {
+    mems: Box<[MemoryStats<'a>]>,
+    segs: Box<[SegmentStats<'a>]>,
+}
+
+impl<'a> LinkStats<'a> {
+    /// メモリ領域ごとの使用状況を、リンカスクリプト内の順に列挙する。
+    pub fn iter_memorys(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &MemoryStats<'a>> + std::iter::FusedIterator + Clone {
+        self.mems.iter()
+    }
+
+    /// セグメントごとのサイズを、メモリ領域内の配置順に列挙する。
+    pub fn iter_segments(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &SegmentStats<'a>> + std::iter::FusedIterator + Clone {
+        self.segs.iter()
+    }
+}
+
+impl std::fmt::Display for LinkStats<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name_width = self
+            .mems
+            .iter()
+            .map(|mem| mem.name.len())
+            .chain(self.segs.iter().map(|seg| seg.name.len()))
+            .max()
+            .unwrap_or(0)
+            .max("Memory".len());
+
+        writeln!(
+            f,
+            "{:<name_width$}  Start    Size     Used     Free     Used%   Largest gap",
+            "Memory"
+        )?;
+        for mem in &self.mems {
+            let gap = match mem.largest_gap {
+                Some(gap) => format!("${:06X}-${:06X} (${:X})", gap.min(), gap.max(), gap.len()),
+                None => "-".to_owned(),
+            };
+            writeln!(
+                f,
+                "{:<name_width$}  ${:06X}  ${:06X}  ${:06X}  ${:06X}  {:5.1}%  {gap}",
+                mem.name,
+                mem.start(),
+                mem.size(),
+                mem.used,
+                mem.free(),
+                mem.used_percent()
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<name_width$}  {:<name_width$}  Start    Size",
+            "Segment", "Memory"
+        )?;
+        for seg in &self.segs {
+            writeln!(
+                f,
+                "{:<name_width$}  {:<name_width$}  ${:06X}  ${:06X}",
+                seg.name, seg.memory, seg.start, seg.size
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// メモリ領域の使用状況。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemoryStats<'a> {
+    name: &'a str,
+    range: NonemptyRange,
+    used: usize,
+    largest_gap: Option<NonemptyRange>,
+}
+
+impl<'a> MemoryStats<'a> {
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// アドレス範囲 (リンカスクリプトで指定されたもの) を返す。
+    pub fn range(&self) -> NonemptyRange {
+        self.range
+    }
+
+    pub fn start(&self) -> usize {
+        self.range.min()
+    }
+
+    pub fn size(&self) -> usize {
+        self.range.len()
+    }
+
+    /// 先頭から、最も後ろにあるセグメントの終端までのサイズを返す (`LinkMemory::used()` と同じ)。
+    ///
+    /// BSS、セグメント間の隙間、ページ境界のパディングも含む。
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// メモリ領域末尾の空きのサイズを返す。途中の隙間は `largest_free_gap()` を参照。
+    pub fn free(&self) -> usize {
+        self.size() - self.used
+    }
+
+    /// 使用率 (%) を返す。
+    pub fn used_percent(&self) -> f64 {
+        self.used as f64 * 100.0 / self.size() as f64
+    }
+
+    /// 最大の連続した空き領域を返す。空きがない場合は `None`。
+    pub fn largest_free_gap(&self) -> Option<NonemptyRange> {
+        self.largest_gap
+    }
+}
+
+/// セグメントのサイズ。分割配置や複製されたセグメントは部分ごとに分かれる。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SegmentStats<'a> {
+    name: &'a str,
+    memory: &'a str,
+    start: usize,
+    size: usize,
+}
+
+impl<'a> SegmentStats<'a> {
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// 配置先のメモリ領域名を返す。
+    pub fn memory_name(&self) -> &'a str {
+        self.memory
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// アドレス空間内に占めるサイズを返す (BSS も含む)。
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::link::link;
+    use crate::range::NonemptyRange;
+    use crate::script::LinkScript;
+    use crate::testutil::{parse_object, TestObject};
+
+    #[test]
+    fn used_matches_link_memory_with_gap() {
+        let script = LinkScript::load(
+            r#"
+MEMORY {
+    ROM: start = $8000, size = $100;
+}
+SEGMENTS {
+    CODE:    load = ROM;
+    VECTORS: load = ROM, start = $80F0;
+}
+"#,
+            "out.bin",
+        )
+        .unwrap();
+        let mut obj = TestObject::new("main");
+        let code_i = obj.section("CODE");
+        obj.literal(code_i, &[0xEA; 0x10]);
+        let vectors_i = obj.section("VECTORS");
+        obj.literal(vectors_i, &[0x00; 6]);
+        let bytes = obj.encode();
+        let objs = [parse_object("main", &bytes)];
+
+        let result = link(&script, &objs);
+        let stats = result.stats();
+        let mem = stats.iter_memorys().next().unwrap();
+
+        assert_eq!(mem.used(), 0xF6);
+        assert_eq!(mem.used(), result.memory("ROM").unwrap().used());
+        assert_eq!(mem.free(), 0x0A);
+        assert_eq!(
+            mem.largest_free_gap(),
+            Some(NonemptyRange::from_min_max(0x8010, 0x80EF))
+        );
+    }
+}
@@ -1,17 +1,19 @@
-use xo65::expr::{Expr, ExprBinary, ExprUnary};
+use xo65::expr::{Expr, ExprBinary, ExprUnary, OpBinary, OpUnary};
 
-use crate::index::{ObjIdx, ObjImportIdx, ObjSectIdx, ObjStrIdx};
+use crate::index::{ExportIdx, ObjIdx, ObjImportIdx, ObjSectIdx, ObjStrIdx, SectIdx};
 use crate::object::Object;
+use crate::script::{LinkScript, LinkScriptSymbolKind};
 
 use super::graph::LinkGraph;
 use super::layout::LinkLayout;
 
 /// 解決済みのシンボルテーブル。
 ///
-/// 全オブジェクトファイルの全インポートシンボルに対する値を保持する。
+/// 全エクスポートシンボルの値、および全オブジェクトファイルの全インポートシンボルに対する値を保持する。
 #[derive(Debug)]
 pub struct SymbolTable {
     table: Box<[Box<[SymbolEntry]>]>,
+    exports: Box<[ExportEntry]>,
 }
 
 impl SymbolTable {
@@ -19,10 +21,34 @@ impl SymbolTable {
         &self.table[obj_i.get()][imp_i.get()]
     }
 
-    pub fn new(objs: &[Object], graph: &LinkGraph, layout: &LinkLayout) -> Self {
-        let exports = build_exports(objs);
+    pub fn export_count(&self) -> usize {
+        self.exports.len()
+    }
+
+    pub fn export(&self, export_i: ExportIdx) -> &ExportEntry {
+        &self.exports[export_i.get()]
+    }
+
+    pub fn enumerate_exports(
+        &self,
+    ) -> impl ExactSizeIterator<Item = (ExportIdx, &ExportEntry)> + std::iter::FusedIterator + Clone
+    {
+        self.exports
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (ExportIdx::new(i), x))
+    }
+
+    pub fn new(
+        script: &LinkScript,
+        objs: &[Object],
+        graph: &LinkGraph,
+        layout: &LinkLayout,
+    ) -> Self {
+        let exports = build_exports(script, objs);
+        check_forced_imports(script, &exports);
 
-        Resolver::new(objs, graph, layout, exports).solve()
+        Resolver::new(script, objs, graph, layout, exports).solve()
     }
 }
 
@@ -31,7 +57,8 @@ impl SymbolTable {
 pub struct SymbolEntry {
     #[allow(dead_code)]
     addr_size: u8,
-    value: i64,
+    value: ExprValue,
+    export_i: ExportIdx,
 }
 
 impl SymbolEntry {
@@ -40,9 +67,55 @@ impl SymbolEntry {
         self.addr_size
     }
 
-    pub fn value(&self) -> i64 {
+    /// 値を、それが相対的なセクションの情報とともに返す。
+    pub fn expr_value(&self) -> ExprValue {
         self.value
     }
+
+    /// このインポートを満たすエクスポートシンボルのインデックスを返す。
+    pub fn export_idx(&self) -> ExportIdx {
+        self.export_i
+    }
+}
+
+/// 解決済みのエクスポートシンボル。
+#[derive(Debug)]
+pub struct ExportEntry {
+    name: String,
+    addr_size: u8,
+    value: ExprValue,
+    /// 定義元のオブジェクトファイル。リンカスクリプトやコマンドラインで定義された場合は `None`。
+    obj_i: Option<ObjIdx>,
+    /// ラベル (アドレス) として定義されたかどうか。
+    label: bool,
+    /// いずれかのオブジェクトファイルからインポートされているかどうか。
+    referenced: bool,
+}
+
+impl ExportEntry {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn addr_size(&self) -> u8 {
+        self.addr_size
+    }
+
+    pub fn value(&self) -> i64 {
+        self.value.value()
+    }
+
+    pub fn object_idx(&self) -> Option<ObjIdx> {
+        self.obj_i
+    }
+
+    pub fn is_label(&self) -> bool {
+        self.label
+    }
+
+    pub fn is_referenced(&self) -> bool {
+        self.referenced
+    }
 }
 
 type Exports<'objs, 'data> = indexmap::IndexMap<&'data str, ExportDesc<'objs>>;
@@ -50,15 +123,44 @@ type Exports<'objs, 'data> = indexmap::IndexMap<&'data str, ExportDesc<'objs>>;
 /// エクスポートシンボルの内容。
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct ExportDesc<'objs> {
-    obj_i: ObjIdx,
     addr_size: u8,
-    expr: &'objs Expr,
+    label: bool,
+    /// リンカスクリプト内の weak なシンボルかどうか (オブジェクトファイルのエクスポートで上書きされうる)。
+    weak: bool,
+    body: ExportBody<'objs>,
 }
 
-/// 全オブジェクトファイルを通じたエクスポートテーブルを構築する。
-fn build_exports<'objs, 'data>(objs: &'objs [Object<'data>]) -> Exports<'objs, 'data> {
+/// エクスポートシンボルの定義元と値。
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum ExportBody<'objs> {
+    /// オブジェクトファイル内で式として定義されたもの。
+    Expr { obj_i: ObjIdx, expr: &'objs Expr },
+    /// リンカスクリプトまたはコマンドラインで定義されたもの。
+    Value(i64),
+}
+
+/// 全オブジェクトファイルおよびリンカスクリプトを通じたエクスポートテーブルを構築する。
+fn build_exports<'objs, 'data>(
+    script: &'data LinkScript,
+    objs: &'objs [Object<'data>],
+) -> Exports<'objs, 'data> {
     let mut exports = Exports::new();
 
+    for sym in script.iter_symbols() {
+        let (value, weak) = match sym.kind() {
+            LinkScriptSymbolKind::Export(value) => (value, false),
+            LinkScriptSymbolKind::Weak(value) => (value, true),
+            LinkScriptSymbolKind::Import => continue,
+        };
+        let desc = ExportDesc {
+            addr_size: sym.addr_size(),
+            label: false,
+            weak,
+            body: ExportBody::Value(value),
+        };
+        exports.insert(sym.name(), desc);
+    }
+
     for (obj_i, obj) in objs.iter().enumerate() {
         let obj_i = ObjIdx::new(obj_i);
 
@@ -66,205 +168,513 @@ fn build_exports<'objs, 'data>(objs: &'objs [Object<'data>]) -> Exports<'objs, '
             let name = ObjStrIdx::new(export.name() as usize);
             let name = obj.query_string(name);
             let desc = ExportDesc {
-                obj_i,
                 addr_size: export.addr_size(),
-                expr: export.expr(),
+                label: export.info().is_label(),
+                weak: false,
+                body: ExportBody::Expr {
+                    obj_i,
+                    expr: export.expr(),
+                },
             };
-            let old = exports.insert(name, desc);
-            assert_eq!(old, None, "duplicate export: '{name}'");
+            match exports.get_mut(name) {
+                None => {
+                    exports.insert(name, desc);
+                }
+                // weak なシンボルはオブジェクトファイルのエクスポートで上書きする (順序は保つ)。
+                Some(old) if old.weak => *old = desc,
+                // それ以外の重複定義はエラー。
+                Some(old) => {
+                    let old_origin = match old.body {
+                        ExportBody::Expr { obj_i, .. } => format!("'{}'", objs[obj_i.get()].name()),
+                        ExportBody::Value(_) => "the linker script".to_owned(),
+                    };
+                    panic!(
+                        "duplicate export: '{name}' is defined in {old_origin} and '{}'",
+                        obj.name()
+                    );
+                }
+            }
         }
     }
 
     exports
 }
 
-/// 全オブジェクトファイルのインポートシンボルを即値に解決するソルバー。
+/// 強制インポートされたシンボルが全てエクスポートされていることを確認する。
+fn check_forced_imports(script: &LinkScript, exports: &Exports) {
+    for sym in script.iter_symbols() {
+        if sym.kind() == LinkScriptSymbolKind::Import {
+            assert!(
+                exports.contains_key(sym.name()),
+                "forced import: symbol '{}' is not exported",
+                sym.name()
+            );
+        }
+    }
+}
+
+/// 全エクスポートシンボルおよび全オブジェクトファイルのインポートシンボルを即値に解決するソルバー。
 #[derive(Debug)]
 struct Resolver<'objs, 'data, 'graph, 'layout> {
+    script: &'data LinkScript,
     objs: &'objs [Object<'data>],
     graph: &'graph LinkGraph,
     layout: &'layout LinkLayout,
     exports: Exports<'objs, 'data>,
+    /// 各オブジェクトファイルの各インポートシンボルに対応する `Exports` 内インデックス。
+    import_to_export: Box<[Box<[ExportIdx]>]>,
 }
 
 impl<'objs, 'data, 'graph, 'layout> Resolver<'objs, 'data, 'graph, 'layout> {
     fn new(
+        script: &'data LinkScript,
         objs: &'objs [Object<'data>],
         graph: &'graph LinkGraph,
         layout: &'layout LinkLayout,
         exports: Exports<'objs, 'data>,
     ) -> Self {
+        // 全オブジェクトファイルのインポートテーブルを走査し、参照すべき Exports 内インデックスを求めておく。
+        let import_to_export: Box<[_]> = objs
+            .iter()
+            .map(|obj| {
+                obj.xo65()
+                    .import_table()
+                    .iter()
+                    .map(|import| {
+                        let name = ObjStrIdx::new(import.name() as usize);
+                        let name = obj.query_string(name);
+                        exports
+                            .get_index_of(name)
+                            .map(ExportIdx::new)
+                            .unwrap_or_else(|| {
+                                panic!("'{}': symbol '{name}' is not exported", obj.name())
+                            })
+                    })
+                    .collect::<Box<[_]>>()
+            })
+            .collect();
+
         Self {
+            script,
             objs,
             graph,
             layout,
             exports,
+            import_to_export,
         }
     }
 
     fn solve(&self) -> SymbolTable {
-        let mut table = Vec::<Vec<ResolveEntry>>::with_capacity(self.objs.len());
-
-        // 全オブジェクトファイルのインポートテーブルを走査し、
-        // 全インポートシンボルを未解決とする (このとき参照すべき Exports 内インデックスを求めておく)。
-        for obj in self.objs {
-            let mut table_row =
-                Vec::<ResolveEntry>::with_capacity(obj.xo65().import_table().count());
-
-            for import in obj.xo65().import_table().iter() {
-                let name = ObjStrIdx::new(import.name() as usize);
-                let name = obj.query_string(name);
-                let export_i = self
-                    .exports
-                    .get_index_of(name)
-                    .unwrap_or_else(|| panic!("'{}': symbol '{name}' is not exported", obj.name()));
-                let entry = ResolveEntry {
-                    addr_size: import.addr_size(),
-                    state: ResolveState::Unresolved { export_i },
-                };
-                table_row.push(entry);
+        // 全エクスポートシンボルを未解決とし、順に解決していく。
+        // states を用いたメモ化再帰。
+        let mut states = vec![ResolveState::Unresolved; self.exports.len()];
+
+        // インポート (強制インポートを含む) されているシンボルは解決できなければならない。
+        let forced = self
+            .script
+            .iter_symbols()
+            .filter(|sym| sym.kind() == LinkScriptSymbolKind::Import)
+            .map(|sym| ExportIdx::new(self.exports.get_index_of(sym.name()).unwrap()));
+        let imported = self.import_to_export.iter().flatten().copied();
+        for export_i in imported.chain(forced) {
+            if let Err(e) = self.resolve_export(&mut states, export_i) {
+                panic!("{e}");
             }
+        }
 
-            table.push(table_row);
+        // どこからも参照されないシンボルは、解決できなければ警告して除外する。
+        for export_i in (0..self.exports.len()).map(ExportIdx::new) {
+            if let Err(e) = self.resolve_export(&mut states, export_i) {
+                let (name, desc) = self.exports.get_index(export_i.get()).unwrap();
+                let origin = match desc.body {
+                    ExportBody::Expr { obj_i, .. } => self.objs[obj_i.get()].name(),
+                    ExportBody::Value(_) => unreachable!("linker script symbols are literals"),
+                };
+                eprintln!("warning: '{origin}': unreferenced symbol '{name}' is ignored: {e}");
+            }
         }
 
-        // 全オブジェクトファイルの全インポートシンボルを解決する。
-        // table を用いたメモ化再帰。
-        for obj_i in (0..self.objs.len()).map(ObjIdx::new) {
-            for imp_i in (0..table[obj_i.get()].len()).map(ObjImportIdx::new) {
-                self.resolve_import(&mut table, obj_i, imp_i);
+        // 除外されたシンボルを詰めたインデックス。
+        let mut new_export_is = Vec::<Option<ExportIdx>>::with_capacity(self.exports.len());
+        let mut export_count = 0;
+        for state in &states {
+            if matches!(state, ResolveState::Done(_)) {
+                new_export_is.push(Some(ExportIdx::new(export_count)));
+                export_count += 1;
+            } else {
+                new_export_is.push(None);
             }
         }
 
-        let table: Box<[_]> = table
-            .into_iter()
-            .map(|row| {
-                row.into_iter()
-                    .map(|entry| {
-                        let addr_size = entry.addr_size;
-                        let ResolveState::Done(value) = entry.state else {
-                            unreachable!();
-                        };
-                        SymbolEntry { addr_size, value }
+        let mut referenced = vec![false; self.exports.len()];
+
+        // 全オブジェクトファイルのインポートシンボルの値は、対応するエクスポートシンボルの値となる。
+        let table: Box<[_]> = self
+            .objs
+            .iter()
+            .zip(&self.import_to_export)
+            .map(|(obj, row)| {
+                obj.xo65()
+                    .import_table()
+                    .iter()
+                    .zip(row)
+                    .map(|(import, &export_i)| {
+                        let export = &self.exports[export_i.get()];
+                        assert_eq!(
+                            import.addr_size(),
+                            export.addr_size,
+                            "address size mismatch for symbol '{}'",
+                            self.exports.get_index(export_i.get()).unwrap().0
+                        );
+                        referenced[export_i.get()] = true;
+                        SymbolEntry {
+                            addr_size: import.addr_size(),
+                            value: states[export_i.get()].value(),
+                            export_i: new_export_is[export_i.get()].unwrap(),
+                        }
                     })
                     .collect::<Box<[_]>>()
             })
             .collect();
 
-        SymbolTable { table }
-    }
-
-    /// 指定されたインポートシンボルを解決する (メモ化再帰)。
-    fn resolve_import(&self, table: &mut ResolveTable, obj_i: ObjIdx, imp_i: ObjImportIdx) -> i64 {
-        // これはエラーが起きない限り参照されない (最適化で消える、はず)。
-        let name = self.objs[obj_i.get()].query_import_name(imp_i);
+        let exports: Box<[_]> = self
+            .exports
+            .iter()
+            .zip(states)
+            .zip(referenced)
+            .filter(|((_, state), _)| matches!(state, ResolveState::Done(_)))
+            .map(|(((&name, desc), state), referenced)| ExportEntry {
+                name: name.to_owned(),
+                addr_size: desc.addr_size,
+                value: state.value(),
+                obj_i: match desc.body {
+                    ExportBody::Expr { obj_i, .. } => Some(obj_i),
+                    ExportBody::Value(_) => None,
+                },
+                label: desc.label,
+                referenced,
+            })
+            .collect();
 
-        let entry = table[obj_i][imp_i];
+        SymbolTable { table, exports }
+    }
 
-        let value = match entry.state {
+    /// 指定されたエクスポートシンボルを解決する (メモ化再帰)。
+    ///
+    /// 解決できない場合、そのシンボルは未解決に戻してエラーを返す。
+    fn resolve_export(
+        &self,
+        states: &mut [ResolveState],
+        export_i: ExportIdx,
+    ) -> Result<ExprValue, String> {
+        let value = match states[export_i.get()] {
             ResolveState::Done(value) => value,
-            ResolveState::Resolving => panic!("circular reference for symbol '{name}'",),
-            ResolveState::Unresolved { export_i } => {
-                table[obj_i][imp_i].state = ResolveState::Resolving;
-                let export = &self.exports[export_i];
-                assert_eq!(
-                    entry.addr_size, export.addr_size,
-                    "address size mismatch for symbol '{name}'",
-                );
-                self.resolve_expr(table, export_i, export.expr)
+            ResolveState::Resolving => {
+                // これはエラーが起きない限り参照されない。
+                let name = self.exports.get_index(export_i.get()).unwrap().0;
+                return Err(format!("circular reference for symbol '{name}'"));
+            }
+            ResolveState::Unresolved => {
+                states[export_i.get()] = ResolveState::Resolving;
+                let export = &self.exports[export_i.get()];
+                let value = match export.body {
+                    ExportBody::Expr { obj_i, expr } => {
+                        self.resolve_expr(states, obj_i, export.addr_size, expr)
+                    }
+                    ExportBody::Value(value) => Ok(ExprValue::literal(value)),
+                };
+                value.inspect_err(|_| states[export_i.get()] = ResolveState::Unresolved)?
             }
         };
 
-        table[obj_i][imp_i].state = ResolveState::Done(value);
+        states[export_i.get()] = ResolveState::Done(value);
 
-        value
+        Ok(value)
     }
 
-    /// 指定された式を解決する (再帰関数)。
-    fn resolve_expr(&self, table: &mut ResolveTable, export_i: usize, expr: &Expr) -> i64 {
+    /// 指定されたオブジェクトファイル内の式を解決する。
+    ///
+    /// `addr_size` は式を含むエクスポートシンボルのアドレスサイズ。
+    fn resolve_expr(
+        &self,
+        states: &mut [ResolveState],
+        obj_i: ObjIdx,
+        addr_size: u8,
+        expr: &Expr,
+    ) -> Result<ExprValue, String> {
         // TODO: unary, binary の式の中では addr_size は統一されてる?特にチェック不要?
 
-        let export = &self.exports[export_i];
-
-        match expr {
-            Expr::Null => panic!("expr is null"),
-            Expr::Literal { value } => *value,
-            Expr::Symbol { import_idx } => {
-                let imp_i_nxt = ObjImportIdx::new(*import_idx as usize);
-                let entry_nxt = table[export.obj_i][imp_i_nxt];
-                assert_eq!(
-                    export.addr_size, entry_nxt.addr_size,
-                    "address size mismatch"
-                );
-                self.resolve_import(table, export.obj_i, imp_i_nxt)
-            }
-            Expr::Section { section_idx } => {
-                let sect_i = self
-                    .graph
-                    .obj_sect_to_sect(export.obj_i, ObjSectIdx::new(*section_idx as usize))
-                    .expect("invalid section expr");
-                self.layout.section(sect_i).start() as i64
+        let mut symbol = |imp_i: ObjImportIdx| {
+            let obj = &self.objs[obj_i.get()];
+            let import =
+                obj.xo65().import_table().get(imp_i.get()).ok_or_else(|| {
+                    format!("'{}': import index out of range: {imp_i}", obj.name())
+                })?;
+            if addr_size != import.addr_size() {
+                return Err("address size mismatch".to_owned());
             }
-            Expr::Unary(unary) => {
-                let ExprUnary { op, expr } = unary.as_ref();
-                let expr_value = self.resolve_expr(table, export_i, expr);
-                op.apply(expr_value)
-            }
-            Expr::Binary(binary) => {
-                let ExprBinary { op, lhs, rhs } = binary.as_ref();
-                let lhs_value = self.resolve_expr(table, export_i, lhs);
-                let rhs_value = self.resolve_expr(table, export_i, rhs);
-                op.apply(lhs_value, rhs_value)
-            }
-        }
+            let export_i = self.import_to_export[obj_i.get()][imp_i.get()];
+            self.resolve_export(states, export_i)
+        };
+
+        try_eval_expr(self.graph, self.layout, obj_i, expr, &mut symbol)
     }
 }
 
-type ResolveTable = Vec<ResolveTableRow>;
-type ResolveTableRow = Vec<ResolveEntry>;
+/// 式の評価結果。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExprValue {
+    value: i64,
+    /// 値がセクション相対 (アドレス) の場合、そのセクション。`.bank` 演算子の評価などに用いる。
+    sect_i: Option<SectIdx>,
+}
 
-impl std::ops::Index<ObjIdx> for ResolveTable {
-    type Output = ResolveTableRow;
+impl ExprValue {
+    fn literal(value: i64) -> Self {
+        Self {
+            value,
+            sect_i: None,
+        }
+    }
 
-    fn index(&self, obj_i: ObjIdx) -> &Self::Output {
-        &self[obj_i.get()]
+    pub fn value(self) -> i64 {
+        self.value
     }
-}
 
-impl std::ops::IndexMut<ObjIdx> for ResolveTable {
-    fn index_mut(&mut self, obj_i: ObjIdx) -> &mut Self::Output {
-        &mut self[obj_i.get()]
+    /// 値がセクション相対の場合、そのセクションを返す。
+    pub fn section_idx(self) -> Option<SectIdx> {
+        self.sect_i
     }
 }
 
-impl std::ops::Index<ObjImportIdx> for ResolveTableRow {
-    type Output = ResolveEntry;
-
-    fn index(&self, imp_i: ObjImportIdx) -> &Self::Output {
-        &self[imp_i.get()]
-    }
+/// オブジェクトファイル `obj_i` 内の式を評価する (再帰関数)。インポートシンボルの値は `symbol` で得る。
+///
+/// セクション相対の値にセクション相対でない値を加減算したものは、同じセクション相対の値とみなす。
+/// `.bank` 演算子の値は、引数がセクション相対の値でなければならず、そのセクションが置かれた
+/// メモリ領域の bank 属性の値となる。
+pub fn eval_expr<F>(
+    graph: &LinkGraph,
+    layout: &LinkLayout,
+    obj_i: ObjIdx,
+    expr: &Expr,
+    symbol: &mut F,
+) -> ExprValue
+where
+    F: FnMut(ObjImportIdx) -> ExprValue,
+{
+    let mut symbol = |imp_i| Ok(symbol(imp_i));
+
+    try_eval_expr(graph, layout, obj_i, expr, &mut symbol).unwrap_or_else(|e| panic!("{e}"))
 }
 
-impl std::ops::IndexMut<ObjImportIdx> for ResolveTableRow {
-    fn index_mut(&mut self, imp_i: ObjImportIdx) -> &mut Self::Output {
-        &mut self[imp_i.get()]
-    }
+/// `eval_expr` と同じだが、評価できない場合は panic せずにエラーを返す。
+fn try_eval_expr<F>(
+    graph: &LinkGraph,
+    layout: &LinkLayout,
+    obj_i: ObjIdx,
+    expr: &Expr,
+    symbol: &mut F,
+) -> Result<ExprValue, String>
+where
+    F: FnMut(ObjImportIdx) -> Result<ExprValue, String>,
+{
+    let value = match expr {
+        Expr::Null => return Err("expr is null".to_owned()),
+        Expr::Literal { value } => ExprValue::literal(*value),
+        Expr::Symbol { import_idx } => symbol(ObjImportIdx::new(*import_idx as usize))?,
+        Expr::Section { section_idx } => {
+            let obj_sect_i = ObjSectIdx::new(*section_idx as usize);
+            let sect_i = graph.obj_sect_to_sect(obj_i, obj_sect_i).ok_or_else(|| {
+                format!("unknown section: obj_i={obj_i}, obj_sect_i={obj_sect_i}")
+            })?;
+            ExprValue {
+                value: layout.section(sect_i).start() as i64,
+                sect_i: Some(sect_i),
+            }
+        }
+        Expr::Unary(unary) => {
+            let ExprUnary { op, expr } = unary.as_ref();
+            let operand = try_eval_expr(graph, layout, obj_i, expr, symbol)?;
+            if *op == OpUnary::Bank {
+                let sect_i = operand
+                    .sect_i
+                    .ok_or("argument for .bank is not segment relative")?;
+                ExprValue::literal(section_bank(graph, layout, sect_i)?)
+            } else {
+                ExprValue::literal(op.apply(operand.value))
+            }
+        }
+        Expr::Binary(binary) => {
+            let ExprBinary { op, lhs, rhs } = binary.as_ref();
+            let lhs = try_eval_expr(graph, layout, obj_i, lhs, symbol)?;
+            let rhs = try_eval_expr(graph, layout, obj_i, rhs, symbol)?;
+            let sect_i = match (op, lhs.sect_i, rhs.sect_i) {
+                (OpBinary::Add, Some(sect_i), None) | (OpBinary::Add, None, Some(sect_i)) => {
+                    Some(sect_i)
+                }
+                (OpBinary::Sub, Some(sect_i), None) => Some(sect_i),
+                _ => None,
+            };
+            ExprValue {
+                value: op.apply(lhs.value, rhs.value),
+                sect_i,
+            }
+        }
+    };
+
+    Ok(value)
 }
 
-/// 解決中のシンボルテーブルエントリ。
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-struct ResolveEntry {
-    addr_size: u8,
-    state: ResolveState,
+/// セクションが置かれたメモリ領域のバンク番号を返す。bank 属性がなければエラー。
+fn section_bank(graph: &LinkGraph, layout: &LinkLayout, sect_i: SectIdx) -> Result<i64, String> {
+    let mem_i = graph.seg_to_mem(graph.sect_to_seg(sect_i));
+    let bank = layout.memory(mem_i).bank().ok_or_else(|| {
+        format!(
+            "memory '{}' is missing the bank attribute (required by .bank)",
+            graph.mem_name(mem_i)
+        )
+    })?;
+
+    Ok(i64::from(bank))
 }
 
 /// シンボルの解決状態。
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum ResolveState {
     /// 解決済み。
-    Done(i64),
+    Done(ExprValue),
     /// 解決中 (循環参照検出用)。
     Resolving,
-    /// 未解決 (`Exports` 内インデックスを保持)。
-    Unresolved { export_i: usize },
+    /// 未解決。
+    Unresolved,
+}
+
+impl ResolveState {
+    /// 解決済みの値を返す。
+    ///
+    /// # Panics
+    ///
+    /// 解決済みでない場合、panic する。
+    fn value(self) -> ExprValue {
+        let Self::Done(value) = self else {
+            unreachable!("symbol is not resolved");
+        };
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::object::ADDR_SIZE_ABS;
+    use crate::script::LinkScript;
+    use crate::testutil::{parse_object, TestExpr, TestObject};
+
+    const SCRIPT: &str = "
+MEMORY {
+    ROM: start = $8000, size = $10, fill = yes;
+}
+SEGMENTS {
+    CODE: load = ROM;
+}
+SYMBOLS {
+    __STACKSIZE__: type = weak, value = $0800;
+}
+";
+
+    /// `__STACKSIZE__` をインポートして参照するオブジェクトファイル。
+    fn user_object() -> Vec<u8> {
+        let mut obj = TestObject::new("user");
+        let sect_i = obj.section("CODE");
+        let imp_i = obj.import("__STACKSIZE__", ADDR_SIZE_ABS);
+        obj.expr(sect_i, 2, TestExpr::Symbol(imp_i));
+        obj.encode()
+    }
+
+    #[test]
+    fn weak_symbol_is_overridden_by_object() {
+        let script = LinkScript::load(SCRIPT, "out.bin").unwrap();
+
+        let mut obj = TestObject::new("stack");
+        obj.export("__STACKSIZE__", TestExpr::Literal(0x400), ADDR_SIZE_ABS);
+        let stack = obj.encode();
+        let user = user_object();
+        let objs = [parse_object("stack", &stack), parse_object("user", &user)];
+
+        let result = crate::link::link(&script, &objs);
+
+        let export = result.export("__STACKSIZE__").unwrap();
+        assert_eq!(export.value(), 0x400);
+        assert_eq!(export.object_name(), Some("stack.o"));
+        let output = result.outputs().next().unwrap();
+        assert_eq!(&output.body()[..2], &[0x00, 0x04]);
+    }
+
+    #[test]
+    fn weak_symbol_is_used_without_object_export() {
+        let script = LinkScript::load(SCRIPT, "out.bin").unwrap();
+
+        let user = user_object();
+        let objs = [parse_object("user", &user)];
+
+        let result = crate::link::link(&script, &objs);
+
+        let export = result.export("__STACKSIZE__").unwrap();
+        assert_eq!(export.value(), 0x800);
+        assert_eq!(export.object_name(), None);
+    }
+
+    #[test]
+    fn unresolvable_unreferenced_export_is_ignored() {
+        let script = LinkScript::load(SCRIPT, "out.bin").unwrap();
+
+        // ROM には bank 属性がないので `.bank` は評価できないが、参照されないのでリンクはできる。
+        let mut obj = TestObject::new("bank");
+        let sect_i = obj.section("CODE");
+        obj.expr(sect_i, 2, TestExpr::Literal(0x1234));
+        obj.export("code", TestExpr::Section(sect_i, 0), ADDR_SIZE_ABS);
+        obj.export(
+            "code_bank",
+            TestExpr::Bank(Box::new(TestExpr::Section(sect_i, 0))),
+            ADDR_SIZE_ABS,
+        );
+        let bank = obj.encode();
+        let objs = [parse_object("bank", &bank)];
+
+        let result = crate::link::link(&script, &objs);
+
+        assert_eq!(result.export("code").unwrap().value(), 0x8000);
+        assert!(result.export("code_bank").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate export: 'value' is defined in 'a.o' and 'b.o'")]
+    fn duplicate_object_exports_are_rejected() {
+        let script = LinkScript::load(SCRIPT, "out.bin").unwrap();
+
+        let encode = |name: &str, value: i32| {
+            let mut obj = TestObject::new(name);
+            obj.export("value", TestExpr::Literal(value), ADDR_SIZE_ABS);
+            obj.encode()
+        };
+        let (a, b) = (encode("a", 1), encode("b", 2));
+        let objs = [parse_object("a", &a), parse_object("b", &b)];
+
+        crate::link::link(&script, &objs);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "duplicate export: '__STACKSIZE__' is defined in the linker script and 'stack.o'"
+    )]
+    fn script_export_clashing_with_object_export_is_rejected() {
+        let script =
+            LinkScript::load(&SCRIPT.replace("type = weak", "type = export"), "out.bin").unwrap();
+
+        let mut obj = TestObject::new("stack");
+        obj.export("__STACKSIZE__", TestExpr::Literal(0x400), ADDR_SIZE_ABS);
+        let stack = obj.encode();
+        let objs = [parse_object("stack", &stack)];
+
+        crate::link::link(&script, &objs);
+    }
 }
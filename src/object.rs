@@ -1,9 +1,31 @@
 //! オブジェクトファイル関連。
 
-use xo65::{section::Section, Xo65};
+use xo65::{line_info::LineInfoKind, section::Section, Xo65};
 
 use crate::index::{ObjImportIdx, ObjSectIdx, ObjStrIdx};
 
+/// アドレスサイズ: ゼロページ (8 bit)。
+pub const ADDR_SIZE_ZP: u8 = 1;
+/// アドレスサイズ: 絶対アドレス (16 bit)。
+pub const ADDR_SIZE_ABS: u8 = 2;
+/// アドレスサイズ: far アドレス (24 bit)。
+pub const ADDR_SIZE_FAR: u8 = 3;
+/// アドレスサイズ: long アドレス (32 bit)。
+pub const ADDR_SIZE_LONG: u8 = 4;
+
+/// アドレスサイズ名 ("zp", "abs" など) をアドレスサイズに変換する。大文字小文字は区別しない。
+pub fn parse_addr_size(s: &str) -> Option<u8> {
+    let addr_size = match s.to_ascii_lowercase().as_str() {
+        "zp" | "zeropage" | "direct" => ADDR_SIZE_ZP,
+        "abs" | "absolute" | "near" => ADDR_SIZE_ABS,
+        "far" => ADDR_SIZE_FAR,
+        "long" | "dword" => ADDR_SIZE_LONG,
+        _ => return None,
+    };
+
+    Some(addr_size)
+}
+
 #[derive(Debug)]
 pub struct Object<'data> {
     name: String,
@@ -64,6 +86,45 @@ impl<'data> Object<'data> {
         self.query_string(ObjStrIdx::new(obj_imp.name() as usize))
     }
 
+    /// インポートするシンボル名を列挙する。
+    pub fn iter_import_names(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &'data str> + std::iter::FusedIterator + Clone + '_ {
+        self.xo65
+            .import_table()
+            .iter()
+            .map(|imp| self.query_string(ObjStrIdx::new(imp.name() as usize)))
+    }
+
+    /// エクスポートするシンボル名を列挙する。
+    pub fn iter_export_names(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &'data str> + std::iter::FusedIterator + Clone + '_ {
+        self.xo65
+            .export_table()
+            .iter()
+            .map(|export| self.query_string(ObjStrIdx::new(export.name() as usize)))
+    }
+
+    /// 行情報のインデックスのリスト (セクションの断片などが持つもの) から、ソース上の位置 ("ファイル名:行")
+    /// を得る。行情報がない場合は `None`。
+    ///
+    /// 複数ある場合は、アセンブラのソース上のもの (マクロ展開などでないもの) を優先する。
+    pub fn query_source_location(&self, lines: &[u32]) -> Option<String> {
+        let infos: Vec<_> = lines
+            .iter()
+            .filter_map(|&i| self.xo65.line_info_table().get(i as usize))
+            .collect();
+        let info = infos
+            .iter()
+            .find(|info| info.kind() == LineInfoKind::Asm)
+            .or_else(|| infos.first())?;
+        let file = self.xo65.file_table().get(info.file() as usize)?;
+        let file_name = self.query_string(ObjStrIdx::new(file.name() as usize));
+
+        Some(format!("{file_name}:{}", info.row()))
+    }
+
     pub fn query_string(&self, i: ObjStrIdx) -> &'data str {
         let s = self
             .xo65
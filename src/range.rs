@@ -35,6 +35,12 @@ impl NonemptyRange {
         !(self.max < other.min || other.max < self.min)
     }
 
+    /// 2 つの範囲の共通部分を返す。共通部分がなければ `None` を返す。
+    pub fn intersection(self, other: Self) -> Option<Self> {
+        self.intersects(other)
+            .then(|| Self::from_min_max(self.min.max(other.min), self.max.min(other.max)))
+    }
+
     pub fn contains(self, x: usize) -> bool {
         self.min <= x && x <= self.max
     }
@@ -1,8 +1,137 @@
 //! リンカスクリプトの AST。
+//!
+//! フォーマッタのため、コメントと空行 (`Trivia`) も保持する。評価時には無視される。
+//! エラー報告のため、ブロック、要素、属性はソース上の位置 (`Span`) を持つ。
+//! INCLUDE は展開されずにそのまま保持される (展開は `include` モジュールで行う)。
+//! IF の条件は評価時に判定される。
+
+/// 各ブロック内の既知の属性キー (整形時はこの順序で出力する)。
+pub const FILES_ATTR_KEYS: &[&str] = &["format", "size"];
+pub const MEMORY_ATTR_KEYS: &[&str] = &[
+    "start",
+    "size",
+    "type",
+    "file",
+    "fill",
+    "fillval",
+    "bank",
+    "fixed",
+    "overlap",
+    "bankcross",
+    "define",
+];
+pub const SEGMENTS_ATTR_KEYS: &[&str] = &[
+    "load",
+    "fit",
+    "split",
+    "replicate",
+    "run",
+    "type",
+    "start",
+    "align",
+    "align_load",
+    "pagecross",
+    "maxsize",
+    "offset",
+    "fillval",
+    "order",
+    "sort",
+    "optional",
+    "define",
+];
+pub const SYMBOLS_ATTR_KEYS: &[&str] = &["type", "value", "addrsize"];
+
+/// 文字列が識別子 (引用符なしで書ける名前) かどうかを返す。
+pub fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// ソース上の範囲 (バイトオフセット)。
+///
+/// 複数のファイルを扱う場合、オフセットは全ファイルを通したもの (`diag::SourceMap` を参照)。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl From<std::ops::Range<usize>> for Span {
+    fn from(range: std::ops::Range<usize>) -> Self {
+        Self {
+            start: range.start,
+            end: range.end,
+        }
+    }
+}
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Script {
-    pub blocks: Box<[Block]>,
+    pub items: Box<[Item]>,
+    /// 最後の項目より後ろのコメントなど。
+    pub trailing: Box<[Trivia]>,
+}
+
+/// トップレベル (および、その中の IF 内) の項目。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Item {
+    Block(Block),
+    Include(Include),
+    If(If<Item>),
+}
+
+/// ブロック内 (および、その中の IF 内) の項目。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BlockItem {
+    Element(Element),
+    If(If<BlockItem>),
+}
+
+/// `IF cond { ... } ELSE { ... }`
+///
+/// ELSE 以降は省略可。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct If<T> {
+    pub cond: Cond,
+    pub then_branch: Branch<T>,
+    pub else_branch: Option<Branch<T>>,
+    /// IF の直前 (および '{' までの間、ELSE の前後) のコメントなど。
+    pub leading: Box<[Trivia]>,
+}
+
+/// IF の分岐 (`{ ... }`)。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Branch<T> {
+    pub items: Box<[T]>,
+    /// 最後の項目より後ろ ('}' の直前) のコメントなど。
+    pub trailing: Box<[Trivia]>,
+}
+
+/// IF の条件式。
+///
+/// 優先順位は高い方から `!`, `&&`, `||`。括弧でグループ化できる。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Cond {
+    /// `defined(NAME)`: シンボルが定義されているか。
+    Defined(String),
+    Not(Box<Cond>),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+}
+
+/// `INCLUDE "path";`
+///
+/// パスはこれを含むファイルのディレクトリからの相対パス (絶対パスも可)。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Include {
+    pub path: String,
+    pub path_span: Span,
+    /// INCLUDE の直前 (および INCLUDE 内) のコメントなど。
+    pub leading: Box<[Trivia]>,
 }
 
 /// `BLOCK_NAME { ... }`
@@ -10,16 +139,30 @@ pub struct Script {
 pub struct Block {
     /// ブロック名。全てのアルファベットは小文字に置換されている。
     pub name: String,
-    pub elems: Box<[Element]>,
+    /// ブロック名の位置。
+    pub name_span: Span,
+    pub items: Box<[BlockItem]>,
+    /// ブロックの直前のコメントなど。
+    pub leading: Box<[Trivia]>,
+    /// 最後の項目より後ろ ('}' の直前) のコメントなど。
+    pub trailing: Box<[Trivia]>,
 }
 
 /// `ELEMENT_NAME: key=value, ...;`
 ///
-/// '=', ',' は省略可。
+/// '=', ',' は省略可。要素名は `"BANK0*"` のように引用符で囲むこともできる (セグメント名のパターン用)。
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Element {
     pub name: String,
+    /// 要素名の位置。
+    pub name_span: Span,
     pub attrs: Box<[Attribute]>,
+    /// 要素の直前のコメントなど。
+    pub leading: Box<[Trivia]>,
+    /// 最後の属性より後ろ (';' の直前) のコメント。
+    pub trailing: Box<[Trivia]>,
+    /// ';' と同じ行にあるコメント ('#' を除く)。
+    pub line_comment: Option<String>,
 }
 
 /// `key=value`
@@ -29,7 +172,20 @@ pub struct Element {
 pub struct Attribute {
     /// キー。全てのアルファベットは小文字に置換されている。
     pub key: String,
+    pub key_span: Span,
     pub value: Value,
+    pub value_span: Span,
+    /// 属性の直前 (および属性内) のコメント。
+    pub leading: Box<[Trivia]>,
+}
+
+/// コメントまたは空行。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Trivia {
+    /// '#' から行末までのコメント ('#' を除く)。
+    Comment(String),
+    /// 1 行以上の空行。
+    BlankLine,
 }
 
 /// リンカスクリプト内の値。
@@ -41,6 +197,10 @@ pub enum Value {
     // NOTE: 便宜上 "zp", "bss" などもここに含める。
     // オリジナルではこれらは文脈依存キーワードになっている (ので、メモリ名に ZP を使ったりできる)。
     Ident(String),
+    /// `A|B|C` (2 つ以上の識別子からなる候補のリスト)
+    Alternatives(Box<[String]>),
+    /// "%S" (スタートアドレス。コマンドラインの `-S` オプションで指定される)
+    StartAddr,
 }
 
 impl Value {
@@ -52,6 +212,14 @@ impl Value {
         }
     }
 
+    /// 整数値を返す。"%S" は `start_addr` に置換される。
+    pub fn as_uint_with(&self, start_addr: u32) -> Option<u32> {
+        match self {
+            Self::StartAddr => Some(start_addr),
+            _ => self.as_uint(),
+        }
+    }
+
     pub fn as_bool(&self) -> Option<bool> {
         if let Self::Bool(x) = self {
             Some(*x)
@@ -75,6 +243,15 @@ impl Value {
             None
         }
     }
+
+    /// 候補のリストを返す。単独の識別子は候補が 1 つのリストとみなす。
+    pub fn as_alternatives(&self) -> Option<Vec<&str>> {
+        match self {
+            Self::Ident(ident) => Some(vec![ident.as_str()]),
+            Self::Alternatives(idents) => Some(idents.iter().map(String::as_str).collect()),
+            _ => None,
+        }
+    }
 }
 
 /// リンカスクリプト内の文字列。
@@ -0,0 +1,261 @@
+//! リンカスクリプトのビルダー。
+
+use anyhow::{bail, ensure, Context as _};
+use indexmap::{indexset, IndexMap, IndexSet};
+
+use crate::index::{MemIdx, OutFileIdx, SegIdx};
+
+use super::{
+    LinkScript, LinkScriptMemory, LinkScriptSegment, LinkScriptSegmentStart, LinkScriptSymbol,
+    LinkScriptSymbolKind,
+};
+
+/// リンカスクリプトをプログラムから組み立てるためのビルダー。
+///
+/// テキストのリンカスクリプトの評価もこれを通して行われるので、各要素に対する検査内容は同じになる。
+/// 要素間の整合性 (メモリ領域とセグメントの種類、ゼロページの範囲、メモリ領域の重なりなど) は
+/// どちらの場合も `LinkScript::check()` で検査される (リンク時にも行われる)。
+#[derive(Debug)]
+pub struct LinkScriptBuilder {
+    // 先頭要素はメイン出力ファイル。
+    outfiles: IndexSet<String>,
+    // 出力ファイルの期待されるサイズ (インデックスは outfiles と同じ。短い場合は指定なし)。
+    outfile_sizes: Vec<Option<usize>>,
+    mems: IndexMap<String, LinkScriptMemory>,
+    segs: IndexMap<String, LinkScriptSegment>,
+    syms: IndexMap<String, LinkScriptSymbol>,
+}
+
+impl LinkScriptBuilder {
+    pub fn new<S: Into<String>>(main_outfile: S) -> Self {
+        Self {
+            outfiles: indexset! { main_outfile.into() },
+            outfile_sizes: Vec::new(),
+            mems: IndexMap::new(),
+            segs: IndexMap::new(),
+            syms: IndexMap::new(),
+        }
+    }
+
+    pub fn main_outfile(&self) -> &str {
+        self.outfiles.first().unwrap()
+    }
+
+    /// 出力ファイルを登録し、そのインデックスを返す。登録済みの場合は既存のインデックスを返す。
+    pub fn outfile<S: Into<String>>(&mut self, path: S) -> OutFileIdx {
+        let (outfile_i, _) = self.outfiles.insert_full(path.into());
+        OutFileIdx::new(outfile_i)
+    }
+
+    /// 出力ファイルの期待されるサイズを設定する (FILES ブロックの size 属性)。
+    pub fn set_outfile_size(&mut self, outfile_i: OutFileIdx, size: usize) -> anyhow::Result<()> {
+        ensure!(
+            outfile_i.get() < self.outfiles.len(),
+            "unknown output file index: {outfile_i}"
+        );
+        if self.outfile_sizes.len() <= outfile_i.get() {
+            self.outfile_sizes.resize(outfile_i.get() + 1, None);
+        }
+        self.outfile_sizes[outfile_i.get()] = Some(size);
+
+        Ok(())
+    }
+
+    /// 登録済みのメモリ領域のインデックスを名前から得る。
+    pub fn memory_idx(&self, name: &str) -> Option<MemIdx> {
+        self.mems.get_index_of(name).map(MemIdx::new)
+    }
+
+    /// 登録済みのメモリ領域名を列挙する。
+    pub fn iter_memory_names(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &str> + std::iter::FusedIterator + Clone {
+        self.mems.keys().map(String::as_str)
+    }
+
+    /// 登録済みのセグメントのインデックスを名前から得る。
+    pub fn segment_idx(&self, name: &str) -> Option<SegIdx> {
+        self.segs.get_index_of(name).map(SegIdx::new)
+    }
+
+    /// メモリ領域を追加し、そのインデックスを返す。
+    pub fn add_memory(&mut self, mem: LinkScriptMemory) -> anyhow::Result<MemIdx> {
+        ensure!(
+            !self.mems.contains_key(&mem.name),
+            "memory '{}' is defined twice",
+            mem.name
+        );
+        if let Some(outfile_i) = mem.outfile_i {
+            ensure!(
+                outfile_i.get() < self.outfiles.len(),
+                "memory '{}': unknown output file index: {outfile_i}",
+                mem.name
+            );
+        }
+
+        let (mem_i, _) = self.mems.insert_full(mem.name.clone(), mem);
+
+        Ok(MemIdx::new(mem_i))
+    }
+
+    /// セグメントを追加し、そのインデックスを返す。
+    pub fn add_segment(&mut self, seg: LinkScriptSegment) -> anyhow::Result<SegIdx> {
+        ensure!(
+            !self.segs.contains_key(&seg.name),
+            "segment '{}' is defined twice",
+            seg.name
+        );
+        for (i, mem_i) in seg.memory_candidates().enumerate() {
+            let mem = self.mems.get_index(mem_i.get()).with_context(|| {
+                format!("segment '{}': unknown memory index: {mem_i}", seg.name)
+            })?;
+            ensure!(
+                !seg.memory_candidates()
+                    .take(i)
+                    .any(|other_i| other_i == mem_i),
+                "segment '{}': memory '{}' appeared twice",
+                seg.name,
+                mem.0
+            );
+        }
+        ensure!(
+            seg.order.iter().all(|pattern| !pattern.is_empty()),
+            "segment '{}': attribute 'order' has an empty entry",
+            seg.name
+        );
+        ensure!(
+            !(seg.split && seg.replicate),
+            "segment '{}': attributes 'split' and 'replicate' cannot be used together",
+            seg.name
+        );
+        // 自動配置されるセグメントの開始アドレスは指定できない (複製されるセグメントは可)。
+        ensure!(
+            seg.alt_mem_is.is_empty()
+                || seg.replicate
+                || !matches!(seg.start, LinkScriptSegmentStart::Addr(_)),
+            "segment '{}': start address cannot be specified with multiple load candidates",
+            seg.name
+        );
+        // パターンは複数のセグメントに展開されるので、開始アドレスは指定できない。
+        ensure!(
+            !(seg.is_pattern() && matches!(seg.start, LinkScriptSegmentStart::Addr(_))),
+            "segment '{}': start address cannot be specified for a segment name pattern",
+            seg.name
+        );
+        // 開始アドレスが絶対アドレスで指定されている場合、それは (全ての候補の) メモリ領域内になければならない。
+        if let LinkScriptSegmentStart::Addr(start) = seg.start {
+            for mem_i in seg.memory_candidates() {
+                let mem = &self.mems[mem_i.get()];
+                ensure!(
+                    mem.range.contains(start),
+                    "segment '{}': start address is out of memory '{}'",
+                    seg.name,
+                    mem.name
+                );
+            }
+        }
+
+        let (seg_i, _) = self.segs.insert_full(seg.name.clone(), seg);
+
+        Ok(SegIdx::new(seg_i))
+    }
+
+    /// シンボルが (値を持つものとして) 定義済みかどうかを返す。強制インポートのみの場合は偽。
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.syms.get(name).is_some_and(|sym| {
+            matches!(
+                sym.kind,
+                LinkScriptSymbolKind::Export(_) | LinkScriptSymbolKind::Weak(_)
+            )
+        })
+    }
+
+    /// 値を持つシンボルを定義する (SYMBOLS ブロックの `type = export`)。
+    ///
+    /// 既に定義されている場合はエラー。強制インポートのみされている場合は定義で置き換える。
+    pub fn add_export<S: Into<String>>(
+        &mut self,
+        name: S,
+        value: i64,
+        addr_size: u8,
+    ) -> anyhow::Result<()> {
+        self.add_definition(name.into(), value, addr_size, false)
+    }
+
+    /// weak なシンボルを定義する (SYMBOLS ブロックの `type = weak`)。
+    ///
+    /// 既に定義されている場合は単に無視される。後から weak でないシンボルとして定義された場合は置き換えられる。
+    /// リンク時には、同名のシンボルをエクスポートするオブジェクトファイルがあればそちらが優先される。
+    pub fn add_weak<S: Into<String>>(
+        &mut self,
+        name: S,
+        value: i64,
+        addr_size: u8,
+    ) -> anyhow::Result<()> {
+        self.add_definition(name.into(), value, addr_size, true)
+    }
+
+    /// シンボルを強制インポートする (SYMBOLS ブロックの `type = import`)。
+    ///
+    /// 同名のシンボルが既に定義されていれば、それがインポートを満たす。
+    pub fn add_import<S: Into<String>>(&mut self, name: S, addr_size: u8) {
+        let name = name.into();
+        self.syms
+            .entry(name.clone())
+            .or_insert_with(|| LinkScriptSymbol {
+                name,
+                kind: LinkScriptSymbolKind::Import,
+                addr_size,
+            });
+    }
+
+    fn add_definition(
+        &mut self,
+        name: String,
+        value: i64,
+        addr_size: u8,
+        weak: bool,
+    ) -> anyhow::Result<()> {
+        let sym = LinkScriptSymbol {
+            name: name.clone(),
+            kind: if weak {
+                LinkScriptSymbolKind::Weak(value)
+            } else {
+                LinkScriptSymbolKind::Export(value)
+            },
+            addr_size,
+        };
+
+        match self.syms.get_mut(&name) {
+            // 未定義、あるいは強制インポートのみされている場合はそのまま定義する。
+            None => {
+                self.syms.insert(name, sym);
+            }
+            Some(old) if old.kind == LinkScriptSymbolKind::Import => *old = sym,
+            // 既に定義されている場合、weak なシンボルは単に無視される。
+            Some(_) if weak => {}
+            // weak なシンボルは weak でないシンボルで置き換えられる。
+            Some(old) if matches!(old.kind, LinkScriptSymbolKind::Weak(_)) => *old = sym,
+            Some(_) => bail!("symbol '{name}' is already defined"),
+        }
+
+        Ok(())
+    }
+
+    pub fn build(self) -> LinkScript {
+        let mut outfile_sizes = self.outfile_sizes;
+        outfile_sizes.resize(self.outfiles.len(), None);
+        let outfiles: Box<[_]> = self.outfiles.into_iter().collect();
+        let mems: Box<_> = self.mems.into_values().collect();
+        let segs: Box<_> = self.segs.into_values().collect();
+        let syms: Box<_> = self.syms.into_values().collect();
+
+        LinkScript {
+            outfiles,
+            outfile_sizes: outfile_sizes.into(),
+            mems,
+            segs,
+            syms,
+        }
+    }
+}
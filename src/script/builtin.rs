@@ -0,0 +1,33 @@
+//! 組み込みのターゲット用リンカスクリプト。
+//!
+//! オリジナルの ld65 に付属するものを、このクレートで対応している機能の範囲に簡略化したもの
+//! (define, run 属性や式は使っていない)。
+
+/// (ターゲット名, リンカスクリプト)
+const BUILTINS: &[(&str, &str)] = &[
+    ("apple2", include_str!("builtin/apple2.cfg")),
+    ("atari", include_str!("builtin/atari.cfg")),
+    ("c64", include_str!("builtin/c64.cfg")),
+    ("nes", include_str!("builtin/nes.cfg")),
+    ("none", include_str!("builtin/none.cfg")),
+    ("pce", include_str!("builtin/pce.cfg")),
+    ("sim6502", include_str!("builtin/sim6502.cfg")),
+    ("sim65c02", include_str!("builtin/sim65c02.cfg")),
+    ("vic20", include_str!("builtin/vic20.cfg")),
+];
+
+/// ターゲット名に対応する組み込みのリンカスクリプトを返す。大文字小文字は区別しない。
+///
+/// 戻り値は `LinkScript::load` などにそのまま渡せる。
+pub fn builtin(target: &str) -> Option<&'static str> {
+    BUILTINS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(target))
+        .map(|&(_, script)| script)
+}
+
+/// 組み込みのリンカスクリプトを持つターゲット名を列挙する。
+pub fn builtin_targets(
+) -> impl ExactSizeIterator<Item = &'static str> + std::iter::FusedIterator + Clone {
+    BUILTINS.iter().map(|&(name, _)| name)
+}
@@ -0,0 +1,192 @@
+//! 評価済みリンカスクリプトの静的検査 (オブジェクトファイルなしで行えるもの)。
+
+use super::{
+    LinkScript, LinkScriptMemory, LinkScriptMemoryType, LinkScriptSegment, LinkScriptSegmentStart,
+    LinkScriptSegmentType,
+};
+
+impl LinkScript {
+    /// オブジェクトファイルに依存しない検査を行い、見つかった問題を全て返す。
+    ///
+    /// * アドレス範囲が重なっているメモリ領域 (`find_memory_overlaps()` を参照)。
+    /// * セグメントの種類とロード先メモリ領域の種類の組み合わせ。
+    /// * 開始アドレスが指定されたセグメントの順序 (同一メモリ領域内で昇順でなければならない)。
+    /// * ゼロページセグメントのアドレス範囲。
+    /// * 出力ファイルの期待されるサイズ (FILES ブロックの size 属性) とメモリ領域のサイズの整合性。
+    pub fn check(&self) -> Box<[LinkScriptIssue]> {
+        let mut issues = Vec::<LinkScriptIssue>::new();
+
+        for overlap in self.find_memory_overlaps() {
+            let [mem_i, other_i] = overlap.memory_idxs();
+            let range = overlap.range();
+            issues.push(LinkScriptIssue::warning(format!(
+                "memory '{}' overlaps memory '{}' at ${:04X}-${:04X}",
+                self.memory(mem_i).name(),
+                self.memory(other_i).name(),
+                range.min(),
+                range.max()
+            )));
+        }
+
+        for seg in self.iter_segments() {
+            // 自動配置されるセグメントは、全ての候補について検査する。
+            for mem_i in seg.memory_candidates() {
+                let mem = self.memory(mem_i);
+                check_segment_type(&mut issues, mem, seg);
+                check_zeropage(&mut issues, mem, seg);
+            }
+        }
+
+        for (mem_i, mem) in self.enumerate_memorys() {
+            // 直前の開始アドレス指定付きセグメント。
+            let mut prev = None::<(&LinkScriptSegment, usize)>;
+
+            let segs = self.iter_segments().filter(|seg| seg.memory_idx() == mem_i);
+            for seg in segs {
+                let LinkScriptSegmentStart::Addr(start) = seg.start() else {
+                    continue;
+                };
+                if let Some((prev_seg, prev_start)) = prev
+                    && start < prev_start
+                {
+                    issues.push(LinkScriptIssue::error(format!(
+                        "segment '{}' (start = ${start:04X}) is placed after segment '{}' (start = ${prev_start:04X}) in memory '{}'",
+                        seg.name(),
+                        prev_seg.name(),
+                        mem.name()
+                    )));
+                }
+                prev = Some((seg, start));
+            }
+        }
+
+        for (outfile_i, outfile) in self.enumerate_outfiles() {
+            let Some(expected) = self.outfile_size(outfile_i) else {
+                continue;
+            };
+            let mems: Vec<_> = self
+                .iter_memorys()
+                .filter(|mem| mem.outfile_idx() == Some(outfile_i))
+                .collect();
+            let max_len: usize = mems.iter().map(|mem| mem.len()).sum();
+            if mems.is_empty() {
+                issues.push(LinkScriptIssue::warning(format!(
+                    "file '{outfile}' has an expected size but no memory is written to it"
+                )));
+            } else if mems.iter().all(|mem| mem.is_filled()) && max_len != expected {
+                // 全て fill 指定なら、出力サイズはメモリ領域のサイズの合計で確定する。
+                issues.push(LinkScriptIssue::error(format!(
+                    "file '{outfile}' is always ${max_len:X} ({max_len}) bytes, but expected size is ${expected:X} ({expected}) bytes"
+                )));
+            } else if max_len < expected {
+                issues.push(LinkScriptIssue::error(format!(
+                    "file '{outfile}' can be at most ${max_len:X} ({max_len}) bytes, but expected size is ${expected:X} ({expected}) bytes"
+                )));
+            }
+        }
+
+        issues.into()
+    }
+}
+
+/// セグメントの種類とロード先メモリ領域の種類の組み合わせを検査する。
+fn check_segment_type(
+    issues: &mut Vec<LinkScriptIssue>,
+    mem: &LinkScriptMemory,
+    seg: &LinkScriptSegment,
+) {
+    // メモリ領域の種類が指定されていなければ検査しない。
+    let Some(mem_ty) = mem.ty() else {
+        return;
+    };
+
+    match (mem_ty, seg.ty()) {
+        // 書き込みが発生するデータを ROM に置くと、実行時に書き換えられない。
+        // (オリジナルでは run 属性で RAM へ転送するが、ここでは未対応)
+        (LinkScriptMemoryType::Ro, LinkScriptSegmentType::Rw) => {
+            issues.push(LinkScriptIssue::warning(format!(
+                "segment '{}' has type 'rw' but is loaded into read-only memory '{}'",
+                seg.name(),
+                mem.name()
+            )))
+        }
+        // BSS を ROM に置いても書き込めないので無意味。
+        (LinkScriptMemoryType::Ro, LinkScriptSegmentType::Bss | LinkScriptSegmentType::Zp) => {
+            issues.push(LinkScriptIssue::error(format!(
+                "segment '{}' has type 'bss'/'zp' but is loaded into read-only memory '{}'",
+                seg.name(),
+                mem.name()
+            )))
+        }
+        _ => {}
+    }
+}
+
+/// ゼロページセグメントのアドレス範囲を検査する。
+///
+/// 実際に $FF を超えるかどうかはリンク時に検査される。
+fn check_zeropage(
+    issues: &mut Vec<LinkScriptIssue>,
+    mem: &LinkScriptMemory,
+    seg: &LinkScriptSegment,
+) {
+    if seg.ty() != LinkScriptSegmentType::Zp {
+        return;
+    }
+
+    let start = match seg.start() {
+        LinkScriptSegmentStart::Addr(start) => start,
+        _ => mem.start(),
+    };
+    if start > 0xFF {
+        issues.push(LinkScriptIssue::error(format!(
+            "zeropage segment '{}' starts at ${start:04X}, out of range $00-$FF",
+            seg.name()
+        )));
+    } else if mem.range().max() > 0xFF {
+        issues.push(LinkScriptIssue::warning(format!(
+            "zeropage segment '{}' is loaded into memory '{}' which extends beyond $FF",
+            seg.name(),
+            mem.name()
+        )));
+    }
+}
+
+/// リンカスクリプトの静的検査で見つかった問題。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LinkScriptIssue {
+    error: bool,
+    message: String,
+}
+
+impl LinkScriptIssue {
+    pub(super) fn warning(message: String) -> Self {
+        Self {
+            error: false,
+            message,
+        }
+    }
+
+    pub(super) fn error(message: String) -> Self {
+        Self {
+            error: true,
+            message,
+        }
+    }
+
+    /// リンクを続行できない問題かどうかを返す (偽なら警告)。
+    pub fn is_error(&self) -> bool {
+        self.error
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for LinkScriptIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let level = if self.error { "error" } else { "warning" };
+        write!(f, "{level}: {}", self.message)
+    }
+}
@@ -0,0 +1,257 @@
+//! リンカスクリプトのエラー報告。
+//!
+//! パース/評価時のエラーにはソース上の位置を付加でき、最終的に
+//! `file:line:col: message` 形式とソースの抜粋からなる `ScriptError` に変換される。
+//!
+//! INCLUDE で複数のファイルを扱うため、位置は全ファイルを通したオフセットとし、
+//! `SourceMap` でファイルとファイル内オフセットに変換する。
+
+use super::ast::Span;
+
+/// ソース上の位置を含むリンカスクリプトのエラー。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScriptError {
+    file_name: String,
+    line: usize,
+    column: usize,
+    message: String,
+    snippet: String,
+}
+
+impl ScriptError {
+    fn new(file_name: &str, source: &str, span: Span, message: String) -> Self {
+        let start = span.start.min(source.len());
+        let end = span.end.clamp(start, source.len());
+
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find(['\n', '\r'])
+            .map_or(source.len(), |i| start + i);
+        let line = source[..start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count() + 1;
+
+        // 範囲が複数行にわたる場合、最初の行の部分のみを示す。
+        let caret_len = source[start..end.min(line_end)].chars().count().max(1);
+        let line_no = line.to_string();
+        let pad = " ".repeat(line_no.len());
+        let snippet = format!(
+            "{pad} |\n{line_no} | {}\n{pad} | {}{}",
+            &source[line_start..line_end],
+            " ".repeat(column - 1),
+            "^".repeat(caret_len)
+        );
+
+        Self {
+            file_name: file_name.to_owned(),
+            line,
+            column,
+            message,
+            snippet,
+        }
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// 行番号 (1 始まり) を返す。
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 桁番号 (1 始まり、文字単位) を返す。
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}\n{}",
+            self.file_name, self.line, self.column, self.message, self.snippet
+        )
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// ソース上の位置が付加されたエラー (ファイル名と行番号への変換前)。
+#[derive(Debug)]
+struct Located {
+    span: Span,
+    message: String,
+}
+
+impl std::fmt::Display for Located {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Located {}
+
+/// 位置付きのエラーを作る。
+pub(super) fn error_at(span: Span, message: String) -> anyhow::Error {
+    Located { span, message }.into()
+}
+
+pub(super) trait ResultExt<T> {
+    /// エラーに位置を付加する。既に位置が付加されている場合、より詳細なものとしてそちらを優先する。
+    fn with_span(self, span: Span) -> anyhow::Result<T>;
+}
+
+impl<T> ResultExt<T> for anyhow::Result<T> {
+    fn with_span(self, span: Span) -> anyhow::Result<T> {
+        self.map_err(|e| {
+            if e.chain().any(|cause| cause.is::<Located>()) {
+                e
+            } else {
+                error_at(span, format!("{e:#}"))
+            }
+        })
+    }
+}
+
+/// 読み込んだリンカスクリプトのソースの集合。
+///
+/// 各ファイルには、全ファイルを通したオフセットの基準値が割り当てられる。
+#[derive(Debug, Default)]
+pub(super) struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+#[derive(Debug)]
+struct SourceFile {
+    name: String,
+    text: String,
+    base: usize,
+}
+
+impl SourceMap {
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+
+    /// ソースを追加し、そのオフセットの基準値を返す。
+    pub(super) fn add(&mut self, name: &str, text: String) -> usize {
+        // ファイル末尾を指す位置が次のファイルの先頭と重ならないよう、1 つ空ける。
+        let base = self
+            .files
+            .last()
+            .map_or(0, |file| file.base + file.text.len() + 1);
+        self.files.push(SourceFile {
+            name: name.to_owned(),
+            text,
+            base,
+        });
+
+        base
+    }
+
+    /// 基準値 `base` のソースの内容を返す。
+    pub(super) fn text(&self, base: usize) -> &str {
+        let file = self.files.iter().find(|file| file.base == base).unwrap();
+        &file.text
+    }
+
+    /// 位置付きのエラーを `ScriptError` に変換する。位置がなければ `None` を返す。
+    pub(super) fn locate(&self, e: &anyhow::Error) -> Option<ScriptError> {
+        let located = e
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Located>())?;
+        let file = self
+            .files
+            .iter()
+            .rev()
+            .find(|file| file.base <= located.span.start)?;
+        let span = Span {
+            start: located.span.start - file.base,
+            end: located.span.end - file.base,
+        };
+
+        Some(ScriptError::new(
+            &file.name,
+            &file.text,
+            span,
+            located.message.clone(),
+        ))
+    }
+}
+
+/// `name` に近い候補があれば "did you mean" の形で返す。なければ空文字列を返す。
+pub(super) fn did_you_mean<'a, I>(name: &str, candidates: I) -> String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    // 大文字小文字の違いは距離に含めない。
+    let name = name.to_ascii_lowercase();
+    let max_dist = (name.chars().count() / 3).max(1);
+
+    let best = candidates
+        .into_iter()
+        .map(|cand| (edit_distance(&name, &cand.to_ascii_lowercase()), cand))
+        .filter(|&(dist, _)| dist <= max_dist)
+        .min_by_key(|&(dist, _)| dist);
+
+    best.map_or_else(String::new, |(_, cand)| {
+        format!(" (did you mean '{cand}'?)")
+    })
+}
+
+/// 2 つの文字列の編集距離 (隣接する 2 文字の入れ替えも 1 回の操作と数える。optimal string alignment)。
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // 2 行前・1 行前・現在の行。
+    let mut prev2 = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut row = vec![0; b.len() + 1];
+
+    for i in 1..=a.len() {
+        row[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (prev[j - 1] + cost).min(prev[j] + 1).min(row[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(prev2[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut prev2, &mut prev);
+        std::mem::swap(&mut prev, &mut row);
+    }
+
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distance_counts_transposition_once() {
+        assert_eq!(edit_distance("laod", "load"), 1);
+        assert_eq!(edit_distance("rmo", "rom"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", "abc"), 0);
+    }
+
+    #[test]
+    fn did_you_mean_suggests_nearest() {
+        let keys = ["load", "start", "size", "type"];
+        assert_eq!(did_you_mean("laod", keys), " (did you mean 'load'?)");
+        assert_eq!(did_you_mean("STRAT", keys), " (did you mean 'start'?)");
+        assert_eq!(
+            did_you_mean("RMO", ["ZP", "RAM", "ROM"]),
+            " (did you mean 'ROM'?)"
+        );
+        assert_eq!(did_you_mean("bank", keys), "");
+        assert_eq!(did_you_mean("x", ["ROM"]), "");
+    }
+}
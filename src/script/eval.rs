@@ -1,48 +1,57 @@
+use std::collections::HashSet;
+
 use anyhow::{anyhow, bail, ensure, Context as _};
-use indexmap::{indexset, IndexMap, IndexSet};
 
-use crate::index::{MemIdx, OutFileIdx};
+use crate::object::{parse_addr_size, ADDR_SIZE_ABS};
 use crate::range::NonemptyRange;
 
+use super::diag::{did_you_mean, error_at, ResultExt as _};
 use super::{
-    ast, LinkScript, LinkScriptMemory, LinkScriptMemoryBuilder, LinkScriptSegment,
-    LinkScriptSegmentBuilder, LinkScriptSegmentStart,
+    ast, LinkScript, LinkScriptBuilder, LinkScriptFit, LinkScriptMemory, LinkScriptMemoryBuilder,
+    LinkScriptMemoryType, LinkScriptParams, LinkScriptSectionSort, LinkScriptSegment,
+    LinkScriptSegmentBuilder, LinkScriptSegmentStart, LinkScriptSegmentType,
 };
 
-/// リンカスクリプトの AST を評価し、結果を返す。
-pub fn eval(script: &ast::Script, main_outfile: &str) -> anyhow::Result<LinkScript> {
-    // 先に重複定義チェックを済ませてしまう。
-    check_dup(script)?;
+/// リンカスクリプトの AST (INCLUDE 展開済みの項目のリスト) を評価し、結果を返す。
+///
+/// IF の条件は、その時点までに定義されたシンボル (外部パラメータおよび先行する SYMBOLS ブロック)
+/// に対して判定される。ただし、ブロック内の IF はそのブロックの評価前にまとめて判定される。
+pub fn eval(
+    items: &[ast::Item],
+    main_outfile: &str,
+    params: &LinkScriptParams,
+) -> anyhow::Result<LinkScript> {
+    let mut ctx = EvalContext::new(main_outfile, params.start_addr());
 
-    let mut ctx = EvalContext::new(main_outfile);
+    // 外部パラメータで与えられたシンボルはリンカスクリプトより先に登録しておく。
+    // (SYMBOLS ブロック内の weak なシンボルを上書きするため)
+    eval_params(&mut ctx, params)?;
 
-    eval_blocks(&mut ctx, &script.blocks)?;
+    eval_items(&mut ctx, items)?;
 
     Ok(ctx.into_script())
 }
 
-/// リンカスクリプト内の重複定義チェック。
-fn check_dup(script: &ast::Script) -> anyhow::Result<()> {
-    // ブロック名に重複があってはならない。
-    if let Some(name) = find_dup_str(script.blocks.iter().map(|block| block.name.as_str())) {
-        bail!("duplicate block: '{name}'");
+/// ブロック内の (条件を満たす) 要素の重複定義チェック。
+fn check_dup(block: &ast::Block, elems: &[&ast::Element]) -> anyhow::Result<()> {
+    // ブロック内の要素名に重複があってはならない。
+    if let Some(elem) = find_dup(elems, |elem| &elem.name) {
+        return Err(error_at(
+            elem.name_span,
+            format!("block '{}': duplicate element: '{}'", block.name, elem.name),
+        ));
     }
 
-    for block in &script.blocks {
-        // ブロック内の要素名に重複があってはならない。
-        if let Some(name) = find_dup_str(block.elems.iter().map(|elem| elem.name.as_str())) {
-            bail!("block '{}': duplicate element: '{name}'", block.name);
-        }
-
-        for elem in &block.elems {
-            // 要素内の属性キーに重複があってはならない。
-            if let Some(key) = find_dup_str(elem.attrs.iter().map(|attr| attr.key.as_str())) {
-                bail!(
-                    "block '{}': element '{}': duplicate attribute: '{key}'",
-                    block.name,
-                    elem.name
-                );
-            }
+    for elem in elems {
+        // 要素内の属性キーに重複があってはならない。
+        if let Some(attr) = find_dup(&elem.attrs, |attr| &attr.key) {
+            return Err(error_at(
+                attr.key_span,
+                format!(
+                    "block '{}': element '{}': duplicate attribute: '{}'",
+                    block.name, elem.name, attr.key
+                ),
+            ));
         }
     }
 
@@ -51,60 +60,151 @@ fn check_dup(script: &ast::Script) -> anyhow::Result<()> {
 
 #[derive(Debug)]
 struct EvalContext {
-    // 先頭要素はメイン出力ファイル。
-    outfiles: IndexSet<String>,
-    mems: IndexMap<String, LinkScriptMemory>,
-    segs: IndexMap<String, LinkScriptSegment>,
+    builder: LinkScriptBuilder,
+    // "%S" の値。
+    start_addr: u32,
+    // 評価済みのブロック名 (重複チェック用)。
+    block_names: HashSet<String>,
 }
 
 impl EvalContext {
-    fn new(main_outfile: &str) -> Self {
+    fn new(main_outfile: &str, start_addr: u32) -> Self {
         Self {
-            outfiles: indexset! { main_outfile.to_owned() },
-            mems: IndexMap::new(),
-            segs: IndexMap::new(),
+            builder: LinkScriptBuilder::new(main_outfile),
+            start_addr,
+            block_names: HashSet::new(),
         }
     }
 
-    fn main_outfile(&self) -> &str {
-        self.outfiles.first().unwrap()
+    fn start_addr(&self) -> u32 {
+        self.start_addr
     }
 
     fn into_script(self) -> LinkScript {
-        let outfiles: Box<[_]> = self.outfiles.into_iter().collect();
-        let mems: Box<_> = self.mems.into_values().collect();
-        let segs: Box<_> = self.segs.into_values().collect();
-
-        LinkScript {
-            outfiles,
-            mems,
-            segs,
-        }
+        self.builder.build()
     }
 }
 
-fn eval_blocks(ctx: &mut EvalContext, blocks: &[ast::Block]) -> anyhow::Result<()> {
-    for block in blocks {
-        eval_block(ctx, block).with_context(|| format!("block '{}' eval error", block.name))?;
+fn eval_items(ctx: &mut EvalContext, items: &[ast::Item]) -> anyhow::Result<()> {
+    for item in items {
+        match item {
+            ast::Item::Block(block) => {
+                // ブロック名に重複があってはならない。
+                if !ctx.block_names.insert(block.name.clone()) {
+                    return Err(error_at(
+                        block.name_span,
+                        format!("duplicate block: '{}'", block.name),
+                    ));
+                }
+                eval_block(ctx, block)
+                    .with_span(block.name_span)
+                    .with_context(|| format!("block '{}' eval error", block.name))?;
+            }
+            ast::Item::If(if_) => eval_items(ctx, select_branch(ctx, if_))?,
+            ast::Item::Include(_) => unreachable!("INCLUDE must be expanded before evaluation"),
+        }
     }
 
     Ok(())
 }
 
 fn eval_block(ctx: &mut EvalContext, block: &ast::Block) -> anyhow::Result<()> {
+    let mut elems = Vec::<&ast::Element>::new();
+    select_elems(ctx, &block.items, &mut elems);
+    check_dup(block, &elems)?;
+
     match block.name.as_str() {
-        "memory" => eval_memory(ctx, block),
-        "segments" => eval_segments(ctx, block),
+        "files" => eval_files(ctx, &elems),
+        "memory" => eval_memory(ctx, &elems),
+        "segments" => eval_segments(ctx, &elems),
+        "symbols" => eval_symbols(ctx, &elems),
         unknown => bail!("unknown block: '{unknown}'"),
     }
 }
 
-fn eval_memory(ctx: &mut EvalContext, block: &ast::Block) -> anyhow::Result<()> {
-    for elem in &block.elems {
+/// ブロック内の項目から、条件を満たす要素を集める。
+fn select_elems<'a>(
+    ctx: &EvalContext,
+    items: &'a [ast::BlockItem],
+    elems: &mut Vec<&'a ast::Element>,
+) {
+    for item in items {
+        match item {
+            ast::BlockItem::Element(elem) => elems.push(elem),
+            ast::BlockItem::If(if_) => select_elems(ctx, select_branch(ctx, if_), elems),
+        }
+    }
+}
+
+/// IF の条件を判定し、選ばれた分岐の項目を返す (ELSE がなく、条件を満たさない場合は空)。
+fn select_branch<'a, T>(ctx: &EvalContext, if_: &'a ast::If<T>) -> &'a [T] {
+    if eval_cond(ctx, &if_.cond) {
+        &if_.then_branch.items
+    } else {
+        if_.else_branch.as_ref().map_or(&[], |branch| &branch.items)
+    }
+}
+
+fn eval_cond(ctx: &EvalContext, cond: &ast::Cond) -> bool {
+    match cond {
+        ast::Cond::Defined(name) => ctx.builder.is_defined(name),
+        ast::Cond::Not(cond) => !eval_cond(ctx, cond),
+        ast::Cond::And(lhs, rhs) => eval_cond(ctx, lhs) && eval_cond(ctx, rhs),
+        ast::Cond::Or(lhs, rhs) => eval_cond(ctx, lhs) || eval_cond(ctx, rhs),
+    }
+}
+
+fn eval_files(ctx: &mut EvalContext, elems: &[&ast::Element]) -> anyhow::Result<()> {
+    for elem in elems {
+        // 要素名は出力ファイル名 ("%O" はメイン出力ファイル)。
+        let path = if elem.name == "%O" {
+            ctx.builder.main_outfile().to_owned()
+        } else {
+            elem.name.clone()
+        };
+        for attr in &elem.attrs {
+            eval_files_attr(ctx, &path, attr)
+                .with_span(attr.value_span)
+                .with_context(|| format!("file '{}' eval error", elem.name))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn eval_files_attr(ctx: &mut EvalContext, path: &str, attr: &ast::Attribute) -> anyhow::Result<()> {
+    let ast::Attribute { key, value, .. } = attr;
+    match key.as_str() {
+        "format" => {
+            // バイナリ以外の形式には対応しない。
+            let value = value
+                .as_ident()
+                .ok_or_else(|| anyhow!("invalid value for file attribute 'format': {value:?}"))?;
+            ensure!(
+                value.eq_ignore_ascii_case("bin"),
+                "unsupported file format: '{value}'"
+            );
+        }
+        "size" => {
+            let value = value
+                .as_uint_with(ctx.start_addr())
+                .ok_or_else(|| anyhow!("invalid value for file attribute 'size': {value:?}"))?;
+            let outfile_i = ctx.builder.outfile(path);
+            ctx.builder.set_outfile_size(outfile_i, value as usize)?;
+        }
+        _ => return Err(unknown_attr("file", attr, ast::FILES_ATTR_KEYS)),
+    }
+
+    Ok(())
+}
+
+fn eval_memory(ctx: &mut EvalContext, elems: &[&ast::Element]) -> anyhow::Result<()> {
+    for elem in elems {
+        check_identifier(elem)?;
         let mem = eval_memory_elem(ctx, elem)
+            .with_span(elem.name_span)
             .with_context(|| format!("memory '{}' eval error", elem.name))?;
-        let old = ctx.mems.insert(elem.name.clone(), mem);
-        assert_eq!(old, None); // 重複はないはず
+        ctx.builder.add_memory(mem).with_span(elem.name_span)?;
     }
 
     Ok(())
@@ -121,60 +221,8 @@ fn eval_memory_elem(
     let mut size = None::<usize>;
 
     for attr in &elem.attrs {
-        let ast::Attribute { key, value } = attr;
-        match key.as_str() {
-            "start" => {
-                let value = value
-                    .as_uint()
-                    .ok_or_else(|| anyhow!("invalid value for memory start address: {value:?}"))?;
-                start = Some(value as usize);
-            }
-            "size" => {
-                let value = value
-                    .as_uint()
-                    .ok_or_else(|| anyhow!("invalid value for memory size: {value:?}"))?;
-                size = Some(value as usize);
-            }
-            "type" => {
-                // 文脈依存キーワード。小文字に統一する。
-                let value = value
-                    .as_ident()
-                    .ok_or_else(|| anyhow!("invalid value for memory type: {value:?}"))?
-                    .to_ascii_lowercase();
-                match value.as_str() {
-                    // ro, rw は単に無視する。
-                    "ro" | "rw" => {}
-                    // その他の値は無効 (特に、memory に zp/bss を指定することはできない)。
-                    invalid => bail!("invalid value for memory type: {invalid}"),
-                }
-            }
-            "fill" => {
-                let value = value.as_bool().ok_or_else(|| {
-                    anyhow!("invalid value for memory attribute 'fill': {value:?}")
-                })?;
-                builder.filled(value);
-            }
-            "fillval" => {
-                let value = value
-                    .as_uint()
-                    .and_then(|value| u8::try_from(value).ok())
-                    .ok_or_else(|| {
-                        anyhow!("invalid value for memory attribute 'fillval': {value:?}")
-                    })?;
-                builder.fill_byte(value);
-            }
-            "file" => {
-                let value = value.as_string().ok_or_else(|| {
-                    anyhow!("invalid value for memory attribute 'file': {value:?}")
-                })?;
-                let outfile = value.format(ctx.main_outfile());
-                ensure!(!outfile.is_empty(), "output filename is empty");
-                let (outfile_i, _) = ctx.outfiles.insert_full(outfile);
-                builder.outfile_i(OutFileIdx::new(outfile_i));
-            }
-            key @ ("bank" | "define") => bail!("attribute '{key}' is not supported"),
-            unknown => bail!("unknown memory attribute: '{unknown}'"),
-        }
+        eval_memory_attr(ctx, &mut builder, &mut start, &mut size, attr)
+            .with_span(attr.value_span)?;
     }
 
     let Some(start) = start else {
@@ -189,22 +237,105 @@ fn eval_memory_elem(
     builder.build().context("failed to build memory")
 }
 
-fn eval_segments(ctx: &mut EvalContext, block: &ast::Block) -> anyhow::Result<()> {
-    for elem in &block.elems {
+fn eval_memory_attr(
+    ctx: &mut EvalContext,
+    builder: &mut LinkScriptMemoryBuilder,
+    start: &mut Option<usize>,
+    size: &mut Option<usize>,
+    attr: &ast::Attribute,
+) -> anyhow::Result<()> {
+    let ast::Attribute { key, value, .. } = attr;
+    match key.as_str() {
+        "start" => {
+            let value = value
+                .as_uint_with(ctx.start_addr())
+                .ok_or_else(|| anyhow!("invalid value for memory start address: {value:?}"))?;
+            *start = Some(value as usize);
+        }
+        "size" => {
+            let value = value
+                .as_uint_with(ctx.start_addr())
+                .ok_or_else(|| anyhow!("invalid value for memory size: {value:?}"))?;
+            *size = Some(value as usize);
+        }
+        "type" => {
+            // 文脈依存キーワード。小文字に統一する。
+            let value = value
+                .as_ident()
+                .ok_or_else(|| anyhow!("invalid value for memory type: {value:?}"))?
+                .to_ascii_lowercase();
+            let ty = match value.as_str() {
+                "ro" => LinkScriptMemoryType::Ro,
+                "rw" => LinkScriptMemoryType::Rw,
+                // その他の値は無効 (特に、memory に zp/bss を指定することはできない)。
+                invalid => bail!("invalid value for memory type: {invalid}"),
+            };
+            builder.ty(ty);
+        }
+        "fill" => {
+            let value = value
+                .as_bool()
+                .ok_or_else(|| anyhow!("invalid value for memory attribute 'fill': {value:?}"))?;
+            builder.filled(value);
+        }
+        "fillval" => {
+            let value = value
+                .as_uint_with(ctx.start_addr())
+                .and_then(|value| u8::try_from(value).ok())
+                .ok_or_else(|| {
+                    anyhow!("invalid value for memory attribute 'fillval': {value:?}")
+                })?;
+            builder.fill_byte(value);
+        }
+        "file" => {
+            let value = value
+                .as_string()
+                .ok_or_else(|| anyhow!("invalid value for memory attribute 'file': {value:?}"))?;
+            let outfile = value.format(ctx.builder.main_outfile());
+            // 空文字列はどのファイルにも出力しないことを表す (RAM 領域など)。
+            if outfile.is_empty() {
+                builder.outfile_i(None);
+            } else {
+                builder.outfile_i(Some(ctx.builder.outfile(outfile)));
+            }
+        }
+        "bank" => {
+            let value = value
+                .as_uint_with(ctx.start_addr())
+                .ok_or_else(|| anyhow!("invalid value for memory attribute 'bank': {value:?}"))?;
+            builder.bank(value);
+        }
+        "overlap" => {
+            let value = value.as_bool().ok_or_else(|| {
+                anyhow!("invalid value for memory attribute 'overlap': {value:?}")
+            })?;
+            builder.overlap(value);
+        }
+        "fixed" => {
+            let value = value
+                .as_bool()
+                .ok_or_else(|| anyhow!("invalid value for memory attribute 'fixed': {value:?}"))?;
+            builder.fixed(value);
+        }
+        "bankcross" => {
+            let value = value.as_bool().ok_or_else(|| {
+                anyhow!("invalid value for memory attribute 'bankcross': {value:?}")
+            })?;
+            builder.bankcross(value);
+        }
+        "define" => return Err(unsupported_attr(attr)),
+        _ => return Err(unknown_attr("memory", attr, ast::MEMORY_ATTR_KEYS)),
+    }
+
+    Ok(())
+}
+
+fn eval_segments(ctx: &mut EvalContext, elems: &[&ast::Element]) -> anyhow::Result<()> {
+    for elem in elems {
         let seg = eval_segments_elem(ctx, elem)
+            .with_span(elem.name_span)
             .with_context(|| format!("segment '{}' eval error", elem.name))?;
-        // 開始アドレスが絶対アドレスで指定されている場合、それはメモリ領域内になければならない。
-        if let LinkScriptSegmentStart::Addr(start) = seg.start {
-            let mem = ctx.mems.get_index(seg.mem_i.get()).unwrap().1;
-            ensure!(
-                mem.range.contains(start),
-                "segment '{}': start address is out of memory '{}'",
-                seg.name,
-                mem.name
-            );
-        }
-        let old = ctx.segs.insert(elem.name.clone(), seg);
-        assert_eq!(old, None); // 重複はないはず
+        ctx.builder.add_segment(seg).with_span(elem.name_span)?;
     }
 
     Ok(())
@@ -220,80 +351,310 @@ fn eval_segments_elem(
     let mut start_specified = false;
 
     for attr in &elem.attrs {
-        let ast::Attribute { key, value } = attr;
-        match key.as_str() {
-            "load" => {
-                let value = value.as_ident().ok_or_else(|| {
-                    anyhow!("invalid value for segment attribute 'load': {value:?}")
+        // パターンは複数のセグメントに展開されるので、アドレスを固定する属性は指定できない。
+        if elem.name.contains(['*', '?']) && matches!(attr.key.as_str(), "start" | "offset") {
+            return Err(error_at(
+                attr.key_span,
+                format!(
+                    "attribute '{}' cannot be used with segment name pattern '{}'",
+                    attr.key, elem.name
+                ),
+            ));
+        }
+        eval_segments_attr(ctx, &mut builder, &mut start_specified, attr)
+            .with_span(attr.value_span)?;
+    }
+
+    builder.build().context("failed to build segment")
+}
+
+fn eval_segments_attr(
+    ctx: &mut EvalContext,
+    builder: &mut LinkScriptSegmentBuilder,
+    start_specified: &mut bool,
+    attr: &ast::Attribute,
+) -> anyhow::Result<()> {
+    let ast::Attribute { key, value, .. } = attr;
+    match key.as_str() {
+        "load" => {
+            // `A|B|C` のように複数の候補を指定できる。
+            let names = value
+                .as_alternatives()
+                .ok_or_else(|| anyhow!("invalid value for segment attribute 'load': {value:?}"))?;
+            let mut mem_is = Vec::with_capacity(names.len());
+            for name in names {
+                let mem_i = ctx.builder.memory_idx(name).ok_or_else(|| {
+                    let suggestion = did_you_mean(name, ctx.builder.iter_memory_names());
+                    anyhow!("unknown memory: '{name}'{suggestion}")
                 })?;
-                let mem_i = ctx
-                    .mems
-                    .get_index_of(value)
-                    .ok_or_else(|| anyhow!("unknown memory: '{value}'"))?;
-                builder.mem_i(MemIdx::new(mem_i));
+                mem_is.push(mem_i);
             }
-            "type" => {
-                // 文脈依存キーワード。小文字に統一する。
-                let value = value
-                    .as_ident()
-                    .ok_or_else(|| anyhow!("invalid value for segment type: {value:?}"))?
-                    .to_ascii_lowercase();
-                match value.as_str() {
-                    // ro, rw は単に無視する。
-                    "ro" | "rw" => {}
-                    // zp と bss は実質同じ (オリジナルでは o65 形式への出力時のみ違いがあるらしい)。
-                    "zp" | "bss" => {
-                        builder.bss(true);
-                    }
-                    "overwrite" => bail!("segment type 'overwrite' is not supported"),
-                    invalid => bail!("invalid segment type: '{invalid}'"),
-                }
-            }
-            "start" => {
-                if start_specified {
-                    bail!("attribute 'start'/'align' appeared twice");
-                }
-                let value = value
-                    .as_uint()
-                    .ok_or_else(|| anyhow!("invalid value for segment start address: {value:?}"))?;
-                builder.start(LinkScriptSegmentStart::Addr(value as usize));
-                start_specified = true;
-            }
-            "align" => {
-                if start_specified {
-                    bail!("attribute 'start'/'align' appeared twice");
-                }
-                let value = value
-                    .as_uint()
-                    .ok_or_else(|| anyhow!("invalid value for segment alignment: {value:?}"))?;
-                builder.start(LinkScriptSegmentStart::Align(value as usize));
-                start_specified = true;
+            builder.mem_i(mem_is[0]);
+            builder.alt_mem_is(&mem_is[1..]);
+        }
+        "fit" => {
+            // 文脈依存キーワード。小文字に統一する。
+            let value = value
+                .as_ident()
+                .ok_or_else(|| anyhow!("invalid value for segment attribute 'fit': {value:?}"))?
+                .to_ascii_lowercase();
+            let fit = match value.as_str() {
+                "first" => LinkScriptFit::First,
+                "best" => LinkScriptFit::Best,
+                invalid => bail!("invalid value for segment attribute 'fit': {invalid}"),
+            };
+            builder.fit(fit);
+        }
+        "split" => {
+            let value = value
+                .as_bool()
+                .ok_or_else(|| anyhow!("invalid value for segment attribute 'split': {value:?}"))?;
+            builder.split(value);
+        }
+        "replicate" => {
+            let value = value.as_bool().ok_or_else(|| {
+                anyhow!("invalid value for segment attribute 'replicate': {value:?}")
+            })?;
+            builder.replicate(value);
+        }
+        "maxsize" => {
+            let value = value.as_uint_with(ctx.start_addr()).ok_or_else(|| {
+                anyhow!("invalid value for segment attribute 'maxsize': {value:?}")
+            })?;
+            builder.max_size(value as usize);
+        }
+        "pagecross" => {
+            let value = value.as_bool().ok_or_else(|| {
+                anyhow!("invalid value for segment attribute 'pagecross': {value:?}")
+            })?;
+            builder.pagecross(value);
+        }
+        "type" => {
+            // 文脈依存キーワード。小文字に統一する。
+            let value = value
+                .as_ident()
+                .ok_or_else(|| anyhow!("invalid value for segment type: {value:?}"))?
+                .to_ascii_lowercase();
+            let ty = match value.as_str() {
+                "ro" => LinkScriptSegmentType::Ro,
+                "rw" => LinkScriptSegmentType::Rw,
+                // zp と bss は出力上は同じ扱い (zp はアドレス範囲のチェックが追加で行われる)。
+                "bss" => LinkScriptSegmentType::Bss,
+                "zp" => LinkScriptSegmentType::Zp,
+                "overwrite" => bail!("segment type 'overwrite' is not supported"),
+                invalid => bail!("invalid segment type: '{invalid}'"),
+            };
+            builder.ty(ty);
+        }
+        "start" => {
+            if *start_specified {
+                bail!("attribute 'start'/'align' appeared twice");
             }
-            "fillval" => {
-                let value = value
-                    .as_uint()
-                    .and_then(|value| u8::try_from(value).ok())
-                    .ok_or_else(|| {
-                        anyhow!("invalid value for segment attribute 'fillval': {value:?}")
-                    })?;
-                builder.fill_byte(value);
+            let value = value
+                .as_uint_with(ctx.start_addr())
+                .ok_or_else(|| anyhow!("invalid value for segment start address: {value:?}"))?;
+            builder.start(LinkScriptSegmentStart::Addr(value as usize));
+            *start_specified = true;
+        }
+        "align" => {
+            if *start_specified {
+                bail!("attribute 'start'/'align' appeared twice");
             }
-            key @ ("align_load" | "define" | "offset" | "optional" | "run") => {
-                bail!("attribute '{key}' is not supported")
+            let value = value
+                .as_uint_with(ctx.start_addr())
+                .ok_or_else(|| anyhow!("invalid value for segment alignment: {value:?}"))?;
+            builder.start(LinkScriptSegmentStart::Align(value as usize));
+            *start_specified = true;
+        }
+        "fillval" => {
+            let value = value
+                .as_uint_with(ctx.start_addr())
+                .and_then(|value| u8::try_from(value).ok())
+                .ok_or_else(|| {
+                    anyhow!("invalid value for segment attribute 'fillval': {value:?}")
+                })?;
+            builder.fill_byte(value);
+        }
+        "order" => {
+            let value = value
+                .as_string()
+                .ok_or_else(|| anyhow!("invalid value for segment attribute 'order': {value:?}"))?
+                .format(ctx.builder.main_outfile());
+            let order: Vec<_> = value.split(',').map(|s| s.trim().to_owned()).collect();
+            builder.order(order);
+        }
+        "sort" => {
+            // 文脈依存キーワード。小文字に統一する。
+            let value = value
+                .as_ident()
+                .ok_or_else(|| anyhow!("invalid value for segment attribute 'sort': {value:?}"))?
+                .to_ascii_lowercase();
+            let sort = match value.as_str() {
+                "object" => LinkScriptSectionSort::Object,
+                "name" => LinkScriptSectionSort::Name,
+                "size" => LinkScriptSectionSort::Size,
+                invalid => bail!("invalid value for segment attribute 'sort': {invalid}"),
+            };
+            builder.sort(sort);
+        }
+        "align_load" | "define" | "offset" | "optional" | "run" => {
+            return Err(unsupported_attr(attr))
+        }
+        _ => return Err(unknown_attr("segment", attr, ast::SEGMENTS_ATTR_KEYS)),
+    }
+
+    Ok(())
+}
+
+fn eval_params(ctx: &mut EvalContext, params: &LinkScriptParams) -> anyhow::Result<()> {
+    for (name, value) in params.iter_defines() {
+        ctx.builder.add_export(name, value, ADDR_SIZE_ABS)?;
+    }
+
+    for (name, addr_size) in params.iter_imports() {
+        ctx.builder.add_import(name, addr_size);
+    }
+
+    Ok(())
+}
+
+fn eval_symbols(ctx: &mut EvalContext, elems: &[&ast::Element]) -> anyhow::Result<()> {
+    for elem in elems {
+        check_identifier(elem)?;
+        eval_symbols_elem(ctx, elem)
+            .with_span(elem.name_span)
+            .with_context(|| format!("symbol '{}' eval error", elem.name))?;
+    }
+
+    Ok(())
+}
+
+/// SYMBOLS ブロック内のシンボルの種類。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SymbolType {
+    Export,
+    Import,
+    Weak,
+}
+
+fn eval_symbols_elem(ctx: &mut EvalContext, elem: &ast::Element) -> anyhow::Result<()> {
+    // type 属性がない場合は export とみなす。
+    let mut ty = SymbolType::Export;
+    let mut value = None::<i64>;
+    let mut addr_size = ADDR_SIZE_ABS;
+
+    for attr in &elem.attrs {
+        eval_symbols_attr(ctx, &mut ty, &mut value, &mut addr_size, attr)
+            .with_span(attr.value_span)?;
+    }
+
+    let name = elem.name.as_str();
+
+    match ty {
+        SymbolType::Import => {
+            ensure!(value.is_none(), "import symbol cannot have a value");
+            ctx.builder.add_import(name, addr_size);
+        }
+        SymbolType::Export | SymbolType::Weak => {
+            let Some(value) = value else {
+                bail!("value not found");
+            };
+            if ty == SymbolType::Weak {
+                ctx.builder.add_weak(name, value, addr_size)?;
+            } else {
+                ctx.builder.add_export(name, value, addr_size)?;
             }
-            unknown => bail!("unknown segment attribute: '{unknown}'"),
         }
     }
 
-    builder.build().context("failed to build segment")
+    Ok(())
+}
+
+fn eval_symbols_attr(
+    ctx: &mut EvalContext,
+    ty: &mut SymbolType,
+    value: &mut Option<i64>,
+    addr_size: &mut u8,
+    attr: &ast::Attribute,
+) -> anyhow::Result<()> {
+    let ast::Attribute {
+        key,
+        value: attr_value,
+        ..
+    } = attr;
+    match key.as_str() {
+        "type" => {
+            // 文脈依存キーワード。小文字に統一する。
+            let attr_value = attr_value
+                .as_ident()
+                .ok_or_else(|| anyhow!("invalid value for symbol type: {attr_value:?}"))?
+                .to_ascii_lowercase();
+            *ty = match attr_value.as_str() {
+                "export" => SymbolType::Export,
+                "import" => SymbolType::Import,
+                "weak" => SymbolType::Weak,
+                invalid => bail!("invalid symbol type: '{invalid}'"),
+            };
+        }
+        "value" => {
+            let attr_value = attr_value
+                .as_uint_with(ctx.start_addr())
+                .ok_or_else(|| anyhow!("invalid value for symbol value: {attr_value:?}"))?;
+            *value = Some(i64::from(attr_value));
+        }
+        "addrsize" => {
+            *addr_size = attr_value
+                .as_ident()
+                .and_then(parse_addr_size)
+                .ok_or_else(|| {
+                    anyhow!("invalid value for symbol attribute 'addrsize': {attr_value:?}")
+                })?;
+        }
+        _ => return Err(unknown_attr("symbol", attr, ast::SYMBOLS_ATTR_KEYS)),
+    }
+
+    Ok(())
+}
+
+/// 要素名が識別子であることを確認する。引用符で囲まれた名前 (パターン) はセグメント名にのみ使える。
+fn check_identifier(elem: &ast::Element) -> anyhow::Result<()> {
+    if ast::is_identifier(&elem.name) {
+        Ok(())
+    } else {
+        Err(error_at(
+            elem.name_span,
+            format!(
+                "invalid name: '{}' (quoted names are only allowed in SEGMENTS and FILES)",
+                elem.name
+            ),
+        ))
+    }
+}
+
+/// 未知の属性のエラーを作る。近い既知の属性があれば提示する。
+fn unknown_attr(kind: &str, attr: &ast::Attribute, known: &[&str]) -> anyhow::Error {
+    let suggestion = did_you_mean(&attr.key, known.iter().copied());
+
+    error_at(
+        attr.key_span,
+        format!("unknown {kind} attribute: '{}'{suggestion}", attr.key),
+    )
+}
+
+/// 未対応の属性のエラーを作る。
+fn unsupported_attr(attr: &ast::Attribute) -> anyhow::Error {
+    error_at(
+        attr.key_span,
+        format!("attribute '{}' is not supported", attr.key),
+    )
 }
 
-/// 文字列のリストから重複した要素を探す。
-fn find_dup_str<'a, I>(it: I) -> Option<&'a str>
+/// リストから、キーが重複した (2 度目に現れた) 要素を探す。
+fn find_dup<T, F>(xs: &[T], f: F) -> Option<&T>
 where
-    I: IntoIterator<Item = &'a str>,
+    F: Fn(&T) -> &str,
 {
-    let mut xs = std::collections::HashSet::<&str>::new();
+    let mut keys = HashSet::<&str>::new();
 
-    it.into_iter().find(|&x| !xs.insert(x))
+    xs.iter().find(|&x| !keys.insert(f(x)))
 }
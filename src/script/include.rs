@@ -0,0 +1,97 @@
+//! リンカスクリプトの INCLUDE の展開。
+
+use std::path::{Path, PathBuf};
+
+use super::ast;
+use super::diag::{error_at, SourceMap};
+use super::parse::parse;
+
+/// リンカスクリプトをパースし、INCLUDE を再帰的に展開した項目のリストを返す。
+///
+/// INCLUDE のパスは、それを含むファイルのディレクトリからの相対パスとして解決される
+/// (`file_name` がファイルパスでない場合はカレントディレクトリから)。
+/// IF 内の INCLUDE も (条件によらず) 展開される。読み込んだソースは `sources` に追加される。
+pub fn load(
+    sources: &mut SourceMap,
+    file_name: &str,
+    text: String,
+) -> anyhow::Result<Box<[ast::Item]>> {
+    // 循環検出用の、現在展開中のファイルのリスト。
+    let mut stack: Vec<PathBuf> = std::fs::canonicalize(file_name).into_iter().collect();
+
+    let items = load_file(sources, &mut stack, file_name, text)?;
+
+    Ok(items.into())
+}
+
+fn load_file(
+    sources: &mut SourceMap,
+    stack: &mut Vec<PathBuf>,
+    file_name: &str,
+    text: String,
+) -> anyhow::Result<Vec<ast::Item>> {
+    let base = sources.add(file_name, text);
+    let script = parse(sources.text(base), base)?;
+
+    let dir = Path::new(file_name).parent().unwrap_or(Path::new(""));
+
+    expand_items(sources, stack, dir, script.items.into_vec())
+}
+
+fn expand_items(
+    sources: &mut SourceMap,
+    stack: &mut Vec<PathBuf>,
+    dir: &Path,
+    items: Vec<ast::Item>,
+) -> anyhow::Result<Vec<ast::Item>> {
+    let mut res = Vec::<ast::Item>::new();
+
+    for item in items {
+        match item {
+            ast::Item::Block(_) => res.push(item),
+            ast::Item::If(mut if_) => {
+                if_.then_branch.items =
+                    expand_items(sources, stack, dir, if_.then_branch.items.into_vec())?.into();
+                if let Some(branch) = &mut if_.else_branch {
+                    let items = std::mem::take(&mut branch.items).into_vec();
+                    branch.items = expand_items(sources, stack, dir, items)?.into();
+                }
+                res.push(ast::Item::If(if_));
+            }
+            ast::Item::Include(include) => {
+                res.extend(load_include(sources, stack, dir, &include)?);
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+fn load_include(
+    sources: &mut SourceMap,
+    stack: &mut Vec<PathBuf>,
+    dir: &Path,
+    include: &ast::Include,
+) -> anyhow::Result<Vec<ast::Item>> {
+    let path = dir.join(&include.path);
+    let text = std::fs::read_to_string(&path).map_err(|e| {
+        error_at(
+            include.path_span,
+            format!("cannot read included file '{}': {e}", path.display()),
+        )
+    })?;
+
+    let canonical = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+    if stack.contains(&canonical) {
+        return Err(error_at(
+            include.path_span,
+            format!("circular include of '{}'", path.display()),
+        ));
+    }
+
+    stack.push(canonical);
+    let items = load_file(sources, stack, &path.display().to_string(), text)?;
+    stack.pop();
+
+    Ok(items)
+}
@@ -1,20 +1,33 @@
 //! リンカスクリプト関連。
 
-use anyhow::Context as _;
-
 use crate::index::{MemIdx, OutFileIdx, SegIdx};
 use crate::range::NonemptyRange;
 
 mod ast;
+mod builder;
+mod builtin;
+mod check;
+mod diag;
 mod eval;
+mod include;
 mod parse;
+mod pattern;
+mod print;
+
+pub use self::builder::LinkScriptBuilder;
+pub use self::builtin::{builtin, builtin_targets};
+pub use self::check::LinkScriptIssue;
+pub use self::diag::ScriptError;
 
 /// 評価済みのリンカスクリプト。
 #[derive(Debug)]
 pub struct LinkScript {
     outfiles: Box<[String]>,
+    // 各出力ファイルの期待されるサイズ (FILES ブロックの size 属性)。
+    outfile_sizes: Box<[Option<usize>]>,
     mems: Box<[LinkScriptMemory]>,
     segs: Box<[LinkScriptSegment]>,
+    syms: Box<[LinkScriptSymbol]>,
 }
 
 impl LinkScript {
@@ -40,6 +53,11 @@ impl LinkScript {
         &self.outfiles[outfile_i.get()]
     }
 
+    /// 出力ファイルの期待されるサイズ (FILES ブロックの size 属性) を返す。指定がない場合は `None`。
+    pub fn outfile_size(&self, outfile_i: OutFileIdx) -> Option<usize> {
+        self.outfile_sizes[outfile_i.get()]
+    }
+
     pub fn memory_count(&self) -> usize {
         self.mems.len()
     }
@@ -86,16 +104,180 @@ impl LinkScript {
         &self.segs[seg_i.get()]
     }
 
+    pub fn symbol_count(&self) -> usize {
+        self.syms.len()
+    }
+
+    /// リンカスクリプトおよび外部パラメータで定義されたシンボルを列挙する。
+    pub fn iter_symbols(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &LinkScriptSymbol> + std::iter::FusedIterator + Clone {
+        self.syms.iter()
+    }
+
+    /// アドレス範囲が重なっているメモリ領域の組を全て返す。
+    ///
+    /// ただし、以下の場合は意図的な重なりとみなして報告しない:
+    ///
+    /// * 両者のバンク番号が共に指定されていて、かつ異なる (同じ CPU ウィンドウを共有するバンクなど)。
+    /// * どちらかに overlap 属性が指定されている。
+    pub fn find_memory_overlaps(&self) -> Box<[LinkScriptMemoryOverlap]> {
+        let mut overlaps = Vec::<LinkScriptMemoryOverlap>::new();
+
+        for (mem_i, mem) in self.enumerate_memorys() {
+            for (other_i, other) in self.enumerate_memorys().skip(mem_i.get() + 1) {
+                // バンク番号が異なれば別のアドレス空間とみなす。
+                let banks_differ = matches!(
+                    (mem.bank(), other.bank()),
+                    (Some(bank), Some(other_bank)) if bank != other_bank
+                );
+                if banks_differ || mem.allows_overlap() || other.allows_overlap() {
+                    continue;
+                }
+                if let Some(range) = mem.range().intersection(other.range()) {
+                    overlaps.push(LinkScriptMemoryOverlap {
+                        mem_is: [mem_i, other_i],
+                        range,
+                    });
+                }
+            }
+        }
+
+        overlaps.into()
+    }
+
     pub fn load(script: &str, main_outfile: &str) -> anyhow::Result<Self> {
-        let script = self::parse::parse(script).context("linker script parse error")?;
-        let script = self::eval::eval(&script, main_outfile).context("linker script eval error")?;
+        Self::load_with_params(script, main_outfile, &LinkScriptParams::default())
+    }
+
+    /// 外部パラメータ (コマンドラインで定義されたシンボルなど) を与えてリンカスクリプトを読み込む。
+    pub fn load_with_params(
+        script: &str,
+        main_outfile: &str,
+        params: &LinkScriptParams,
+    ) -> anyhow::Result<Self> {
+        Self::load_source("<linker script>", script, main_outfile, params)
+    }
+
+    /// ファイル名を指定してリンカスクリプトを読み込む。
+    ///
+    /// `INCLUDE "path";` は `file_name` のディレクトリからの相対パスとして解決される。
+    /// `IF defined(NAME) { ... } ELSE { ... }` の条件は、それまでに定義されたシンボル
+    /// (`-D` で定義したものを含む) により判定される。
+    /// ソース上の位置が特定できるエラーは `ScriptError` (`file:line:col: message` 形式) として返される。
+    pub fn load_source(
+        file_name: &str,
+        script: &str,
+        main_outfile: &str,
+        params: &LinkScriptParams,
+    ) -> anyhow::Result<Self> {
+        let mut sources = self::diag::SourceMap::new();
+
+        let items = self::include::load(&mut sources, file_name, script.to_owned())
+            .map_err(|e| located_error(e, &sources, "linker script parse error"))?;
+        let script = self::eval::eval(&items, main_outfile, params)
+            .map_err(|e| located_error(e, &sources, "linker script eval error"))?;
 
         Ok(script)
     }
 }
 
+/// リンカスクリプトを整形した文字列を返す。コメントは保持され、INCLUDE は展開しない。
+///
+/// 整形規則は `print` モジュールを参照。`file_name` はエラー報告にのみ使われる。
+pub fn format(file_name: &str, script: &str) -> anyhow::Result<String> {
+    let mut sources = self::diag::SourceMap::new();
+    let base = sources.add(file_name, script.to_owned());
+
+    let ast = self::parse::parse(script, base)
+        .map_err(|e| located_error(e, &sources, "linker script parse error"))?;
+
+    Ok(self::print::print(&ast))
+}
+
+/// 位置付きのエラーは `ScriptError` に変換し、それ以外は `context` を付けて返す。
+fn located_error(
+    e: anyhow::Error,
+    sources: &self::diag::SourceMap,
+    context: &'static str,
+) -> anyhow::Error {
+    match sources.locate(&e) {
+        Some(e) => e.into(),
+        None => e.context(context),
+    }
+}
+
+/// リンカスクリプトの評価時に外部から与えるパラメータ。
+///
+/// コマンドラインの `-D`, `-u`, `-S` オプションなどに相当する。
+#[derive(Clone, Debug)]
+pub struct LinkScriptParams {
+    defines: Vec<(String, i64)>,
+    imports: Vec<(String, u8)>,
+    start_addr: u32,
+}
+
+impl Default for LinkScriptParams {
+    fn default() -> Self {
+        Self {
+            defines: Vec::new(),
+            imports: Vec::new(),
+            start_addr: Self::DEFAULT_START_ADDR,
+        }
+    }
+}
+
+impl LinkScriptParams {
+    /// "%S" のデフォルト値 (オリジナルと同じ)。
+    pub const DEFAULT_START_ADDR: u32 = 0x200;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// リンカスクリプト内の "%S" の値を設定する (`-S addr`)。
+    pub fn set_start_addr(&mut self, start_addr: u32) -> &mut Self {
+        self.start_addr = start_addr;
+        self
+    }
+
+    pub fn start_addr(&self) -> u32 {
+        self.start_addr
+    }
+
+    /// シンボルを定義する (`-D name=value`)。
+    ///
+    /// リンカスクリプトの SYMBOLS ブロック内の weak なシンボルより優先される。
+    pub fn define_symbol<S: Into<String>>(&mut self, name: S, value: i64) -> &mut Self {
+        self.defines.push((name.into(), value));
+        self
+    }
+
+    /// シンボルを強制的にインポートする (`-u name`)。
+    pub fn force_import<S: Into<String>>(&mut self, name: S, addr_size: u8) -> &mut Self {
+        self.imports.push((name.into(), addr_size));
+        self
+    }
+
+    pub fn iter_defines(
+        &self,
+    ) -> impl ExactSizeIterator<Item = (&str, i64)> + std::iter::FusedIterator + Clone {
+        self.defines
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+    }
+
+    pub fn iter_imports(
+        &self,
+    ) -> impl ExactSizeIterator<Item = (&str, u8)> + std::iter::FusedIterator + Clone {
+        self.imports
+            .iter()
+            .map(|(name, addr_size)| (name.as_str(), *addr_size))
+    }
+}
+
 /// リンカスクリプトで定義されたメモリ領域。
-#[derive(Debug, Eq, PartialEq, derive_builder::Builder)]
+#[derive(Clone, Debug, Eq, PartialEq, derive_builder::Builder)]
 pub struct LinkScriptMemory {
     #[builder(setter(into))]
     name: String,
@@ -104,9 +286,23 @@ pub struct LinkScriptMemory {
     filled: bool,
     #[builder(default = 0)]
     fill_byte: u8,
+    #[builder(default = None, setter(strip_option))]
+    ty: Option<LinkScriptMemoryType>,
+    #[builder(default = None, setter(strip_option))]
+    bank: Option<u32>,
+    // 常にマップされている (切り替えられない) バンクかどうか。
+    #[builder(default = false)]
+    fixed: bool,
+    // 他のメモリ領域との重なりを意図したものとして許可するかどうか。
+    #[builder(default = false)]
+    overlap: bool,
+    // セクションが 65816 のバンク境界 (64 KiB) をまたぐことを許可するかどうか。
+    #[builder(default = true)]
+    bankcross: bool,
     // file 属性がない場合、メインの出力ファイルを指す。
-    #[builder(default = OutFileIdx::new(0))]
-    outfile_i: OutFileIdx,
+    // file 属性が空文字列の場合、どのファイルにも出力されない (None)。
+    #[builder(default = Some(OutFileIdx::new(0)))]
+    outfile_i: Option<OutFileIdx>,
 }
 
 impl LinkScriptMemory {
@@ -135,23 +331,100 @@ impl LinkScriptMemory {
         self.fill_byte
     }
 
-    pub fn outfile_idx(&self) -> OutFileIdx {
+    /// メモリ領域の種類を返す。type 属性がない場合は `None`。
+    pub fn ty(&self) -> Option<LinkScriptMemoryType> {
+        self.ty
+    }
+
+    /// バンク番号を返す。bank 属性がない場合は `None`。
+    pub fn bank(&self) -> Option<u32> {
+        self.bank
+    }
+
+    /// 常にマップされている (切り替えられない) バンクかどうか (fixed 属性) を返す。
+    pub fn is_fixed(&self) -> bool {
+        self.fixed
+    }
+
+    /// 切り替え可能なバンクかどうか、すなわち bank 属性があり、fixed 属性がないかどうかを返す。
+    pub fn is_switchable(&self) -> bool {
+        self.bank.is_some() && !self.fixed
+    }
+
+    /// 他のメモリ領域とのアドレス範囲の重なりが許可されているかどうかを返す。
+    pub fn allows_overlap(&self) -> bool {
+        self.overlap
+    }
+
+    /// セクションが 64 KiB のバンク境界をまたぐことを許可するかどうか (bankcross 属性) を返す。
+    ///
+    /// 既定では許可される。許可される場合も、またぐセクションがあれば警告が出る
+    /// (`bankcross = no` の場合はエラー)。
+    pub fn allows_bank_cross(&self) -> bool {
+        self.bankcross
+    }
+
+    /// 出力先ファイルのインデックスを返す。どのファイルにも出力されない場合は `None`。
+    pub fn outfile_idx(&self) -> Option<OutFileIdx> {
         self.outfile_i
     }
 }
 
+/// アドレス範囲が重なっているメモリ領域の組。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LinkScriptMemoryOverlap {
+    mem_is: [MemIdx; 2],
+    range: NonemptyRange,
+}
+
+impl LinkScriptMemoryOverlap {
+    /// 重なっているメモリ領域のインデックスを (リンカスクリプト内の順に) 返す。
+    pub fn memory_idxs(&self) -> [MemIdx; 2] {
+        self.mem_is
+    }
+
+    /// 重なっているアドレス範囲を返す。
+    pub fn range(&self) -> NonemptyRange {
+        self.range
+    }
+}
+
 /// リンカスクリプトで定義されたセグメント。
-#[derive(Debug, Eq, PartialEq, derive_builder::Builder)]
+///
+/// 名前に `*`, `?` を含むものはセグメント名のパターンを表す (`LinkScript::expand_segment_patterns()` を参照)。
+#[derive(Clone, Debug, Eq, PartialEq, derive_builder::Builder)]
 pub struct LinkScriptSegment {
     #[builder(setter(into))]
     name: String,
     #[builder(default = LinkScriptSegmentStart::Unspecified)]
     start: LinkScriptSegmentStart,
-    #[builder(default = false)]
-    bss: bool,
+    #[builder(default = LinkScriptSegmentType::Ro)]
+    ty: LinkScriptSegmentType,
     #[builder(default = None, setter(strip_option))]
     fill_byte: Option<u8>,
     mem_i: MemIdx,
+    // load 属性で指定された 2 番目以降の候補メモリ領域 (自動配置用)。
+    #[builder(default, setter(into))]
+    alt_mem_is: Box<[MemIdx]>,
+    #[builder(default = LinkScriptFit::First)]
+    fit: LinkScriptFit,
+    // セクション単位で候補のメモリ領域に分割配置するかどうか。
+    #[builder(default = false)]
+    split: bool,
+    // 全ての候補のメモリ領域に (同じアドレスで) 複製して配置するかどうか。
+    #[builder(default = false)]
+    replicate: bool,
+    // order 属性 (オブジェクトファイル名のパターンのリスト)。空なら指定なし。
+    #[builder(default, setter(into))]
+    order: Box<[String]>,
+    #[builder(default = LinkScriptSectionSort::Object)]
+    sort: LinkScriptSectionSort,
+    // セクションがページ境界 (256 バイト) をまたぐことを許可するかどうか。
+    #[builder(default = true)]
+    pagecross: bool,
+    // サイズの上限 (maxsize 属性)。
+    #[builder(default = None, setter(strip_option))]
+    max_size: Option<usize>,
 }
 
 impl LinkScriptSegment {
@@ -159,21 +432,188 @@ impl LinkScriptSegment {
         &self.name
     }
 
+    /// 名前がパターンかどうかを返す。
+    pub fn is_pattern(&self) -> bool {
+        self.name.contains(['*', '?'])
+    }
+
+    /// 既定ルール (他のどれにも対応しないセグメントを受け入れるもの) かどうかを返す。
+    pub fn is_default_rule(&self) -> bool {
+        self.name == "*"
+    }
+
     pub fn start(&self) -> LinkScriptSegmentStart {
         self.start
     }
 
+    pub fn ty(&self) -> LinkScriptSegmentType {
+        self.ty
+    }
+
+    /// ファイルへの出力を伴わないセグメントかどうかを返す。
     pub fn is_bss(&self) -> bool {
-        self.bss
+        self.ty.is_bss()
     }
 
     pub fn fill_byte(&self) -> Option<u8> {
         self.fill_byte
     }
 
+    /// ロード先メモリ領域を返す。候補が複数ある場合は最初のもの。
     pub fn memory_idx(&self) -> MemIdx {
         self.mem_i
     }
+
+    /// ロード先メモリ領域の候補を列挙する。
+    ///
+    /// 候補が複数ある場合、リンク時にセグメントのサイズに応じていずれかに自動配置される。
+    pub fn memory_candidates(
+        &self,
+    ) -> impl ExactSizeIterator<Item = MemIdx> + std::iter::FusedIterator + Clone + '_ {
+        (0..self.alt_mem_is.len() + 1).map(|i| {
+            if i == 0 {
+                self.mem_i
+            } else {
+                self.alt_mem_is[i - 1]
+            }
+        })
+    }
+
+    /// 自動配置の方式 (fit 属性) を返す。
+    pub fn fit(&self) -> LinkScriptFit {
+        self.fit
+    }
+
+    /// セクション単位で分割配置されるかどうか (split 属性) を返す。
+    ///
+    /// 真の場合、セクションは候補のメモリ領域に順に詰められ、収まらなくなった時点で次の候補に移る。
+    pub fn is_split(&self) -> bool {
+        self.split
+    }
+
+    /// 全ての候補のメモリ領域に複製して配置されるかどうか (replicate 属性) を返す。
+    ///
+    /// 真の場合、各候補には同じ内容が同じアドレスに置かれ、シンボルはそのアドレスに解決される
+    /// (バンクごとのトランポリンなど)。
+    pub fn is_replicated(&self) -> bool {
+        self.replicate
+    }
+
+    /// order 属性で指定されたオブジェクトファイル名のパターンを列挙する。
+    pub fn iter_order(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &str> + std::iter::FusedIterator + Clone {
+        self.order.iter().map(String::as_str)
+    }
+
+    /// セグメント内のセクションの並べ方 (sort 属性) を返す。
+    pub fn section_sort(&self) -> LinkScriptSectionSort {
+        self.sort
+    }
+
+    /// セクションがページ境界をまたぐことを許可するかどうか (pagecross 属性) を返す。
+    ///
+    /// 偽の場合、ページ境界をまたぐセクションは次のページの先頭へ移される。
+    pub fn allows_page_cross(&self) -> bool {
+        self.pagecross
+    }
+
+    /// サイズの上限 (maxsize 属性) を返す。指定がない場合は `None`。
+    ///
+    /// 分割配置されたセグメントは全部分の合計、複製されたセグメントは 1 つ分のサイズに対する上限となる。
+    pub fn max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+}
+
+/// リンカスクリプトで定義されたメモリ領域の種類。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkScriptMemoryType {
+    /// 読み取り専用 (ROM など)。
+    Ro,
+    /// 読み書き可能 (RAM など)。
+    Rw,
+}
+
+/// リンカスクリプトで定義されたセグメントの種類。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkScriptSegmentType {
+    /// 読み取り専用のデータ (デフォルト)。
+    Ro,
+    /// 書き込みが発生しうるデータ。
+    Rw,
+    /// 初期値を持たないデータ。ファイルへは出力されない。
+    Bss,
+    /// ゼロページ上の初期値を持たないデータ。ファイルへは出力されない。
+    Zp,
+}
+
+impl LinkScriptSegmentType {
+    /// ファイルへの出力を伴わない種類かどうかを返す。
+    pub fn is_bss(self) -> bool {
+        matches!(self, Self::Bss | Self::Zp)
+    }
+}
+
+/// 候補が複数あるセグメントの自動配置の方式 (fit 属性)。
+///
+/// セグメントはリンカスクリプト内の順に配置される。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkScriptFit {
+    /// 収まる最初の候補に配置する (デフォルト)。
+    First,
+    /// 収まる候補のうち、空き容量が最も小さいものに配置する。
+    Best,
+}
+
+/// セグメント内のセクションの並べ方 (sort 属性)。
+///
+/// order 属性がある場合、その各グループ内での並べ方となる。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkScriptSectionSort {
+    /// オブジェクトファイルの (コマンドライン上の) 順 (デフォルト)。
+    Object,
+    /// オブジェクトファイル名の順。
+    Name,
+    /// セクションサイズの大きい順。
+    Size,
+}
+
+/// リンカスクリプト (SYMBOLS ブロック) および外部パラメータで定義されたシンボル。
+///
+/// リンカスクリプト内/外部パラメータでの上書きは評価時に解決済み。
+/// 上書きされなかった weak なシンボルは `Weak` のまま残り、リンク時にオブジェクトファイルのエクスポートで上書きされうる。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LinkScriptSymbol {
+    name: String,
+    kind: LinkScriptSymbolKind,
+    /// アドレスサイズ (オブジェクトファイル内の値と同じ形式)。
+    addr_size: u8,
+}
+
+impl LinkScriptSymbol {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> LinkScriptSymbolKind {
+        self.kind
+    }
+
+    pub fn addr_size(&self) -> u8 {
+        self.addr_size
+    }
+}
+
+/// リンカスクリプトで定義されたシンボルの種類。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkScriptSymbolKind {
+    /// 値を持つエクスポートシンボル。
+    Export(i64),
+    /// 値を持つ weak なシンボル。同名のシンボルをエクスポートするオブジェクトファイルがあれば、そちらが優先される。
+    Weak(i64),
+    /// 強制インポート。いずれかのオブジェクトファイルがエクスポートしていなければならない。
+    Import,
 }
 
 /// リンカスクリプトで定義されたセグメントの開始アドレス指定。
@@ -1,114 +1,374 @@
-use anyhow::anyhow;
 use winnow::{
-    ascii::{multispace1 as ws1, Caseless},
-    combinator::{alt, cut_err, delimited, dispatch, fail, opt, peek, preceded, repeat, seq},
+    ascii::{multispace0 as ws0, multispace1 as ws1, Caseless},
+    combinator::{
+        alt, cut_err, delimited, dispatch, fail, not, opt, peek, preceded, repeat, terminated,
+    },
+    error::{ContextError, ErrMode, StrContext, StrContextValue},
+    stream::{LocatingSlice, Stateful},
     token::{any, one_of, take_till, take_while},
     Parser as _,
 };
 
 use super::ast;
+use super::diag::error_at;
 
 /// リンカスクリプトをパースし、AST を返す。
-pub fn parse(s: &str) -> anyhow::Result<ast::Script> {
-    script.parse(s).map_err(|e| anyhow!("{e}"))
+///
+/// AST 内の位置およびエラーの位置には `base` が加算される (`diag::SourceMap` を参照)。
+pub fn parse(s: &str, base: usize) -> anyhow::Result<ast::Script> {
+    let input = Stateful {
+        input: LocatingSlice::new(s),
+        state: base,
+    };
+    script.parse(input).map_err(|e| {
+        let offset = base + e.offset();
+        let detail = e.inner().to_string().replace('\n', ", ");
+        let message = if detail.is_empty() {
+            "syntax error".to_owned()
+        } else {
+            format!("syntax error: {detail}")
+        };
+        error_at(ast::Span::from(offset..offset + 1), message)
+    })
 }
 
+/// 状態として位置に加算するオフセットを持つ。
+type Input<'a> = Stateful<LocatingSlice<&'a str>, usize>;
+
 type ParseResult<T> = winnow::ModalResult<T>;
 
-fn script(input: &mut &str) -> ParseResult<ast::Script> {
-    delimited(ign, block0, ign)
-        .map(|blocks| ast::Script { blocks })
-        .parse_next(input)
+fn script(input: &mut Input<'_>) -> ParseResult<ast::Script> {
+    let (items, trailing) = item_list(input, item)?;
+
+    Ok(ast::Script {
+        items: items.into(),
+        trailing: trailing.into(),
+    })
 }
 
-fn block0(input: &mut &str) -> ParseResult<Box<[ast::Block]>> {
-    let mut blocks = Vec::<ast::Block>::new();
+/// 直前のコメントなどを持つ項目。
+trait HasLeading {
+    fn leading_mut(&mut self) -> &mut Box<[ast::Trivia]>;
+}
 
-    match opt(block).parse_next(input) {
-        Ok(Some(first)) => blocks.push(first),
-        Ok(None) => return Ok(blocks.into()),
-        Err(e) => return Err(e),
+impl HasLeading for ast::Item {
+    fn leading_mut(&mut self) -> &mut Box<[ast::Trivia]> {
+        match self {
+            Self::Block(block) => &mut block.leading,
+            Self::Include(include) => &mut include.leading,
+            Self::If(if_) => &mut if_.leading,
+        }
     }
+}
 
-    let remain: Vec<_> = repeat(0.., preceded(ign, block)).parse_next(input)?;
-    blocks.extend(remain);
+impl HasLeading for ast::BlockItem {
+    fn leading_mut(&mut self) -> &mut Box<[ast::Trivia]> {
+        match self {
+            Self::Element(elem) => &mut elem.leading,
+            Self::If(if_) => &mut if_.leading,
+        }
+    }
+}
 
-    Ok(blocks.into())
+/// 項目の並びを読み取る。
+///
+/// 各項目の直前のコメントなどはその項目に付け、最後の項目より後ろのものは別途返す。
+fn item_list<'a, T: HasLeading>(
+    input: &mut Input<'a>,
+    item: fn(&mut Input<'a>) -> ParseResult<T>,
+) -> ParseResult<(Vec<T>, Vec<ast::Trivia>)> {
+    let mut items = Vec::<T>::new();
+
+    let mut pending = trivia.parse_next(input)?;
+    while let Some(mut x) = opt(item).parse_next(input)? {
+        let leading = x.leading_mut();
+        pending.extend(leading.iter().cloned());
+        *leading = std::mem::take(&mut pending).into();
+        items.push(x);
+        pending = trivia.parse_next(input)?;
+    }
+
+    Ok((items, pending))
 }
 
-fn block(input: &mut &str) -> ParseResult<ast::Block> {
-    seq! { ast::Block {
-        name: identifier.map(|s| s.to_ascii_lowercase()), // 小文字に統一
-        _: cut_err(delimited(ign, '{', ign)),
-        elems: cut_err(element0),
-        _: cut_err(preceded(ign, '}')),
-    }}
-    .parse_next(input)
+fn item(input: &mut Input<'_>) -> ParseResult<ast::Item> {
+    let (name, name_span) = identifier
+        .map(|s| s.to_ascii_lowercase()) // 小文字に統一
+        .with_span()
+        .parse_next(input)?;
+    let name_span = span(input, name_span);
+
+    match name.as_str() {
+        "include" => include(input).map(ast::Item::Include),
+        "if" => if_rest(input, item).map(ast::Item::If),
+        _ => block(input, name, name_span).map(ast::Item::Block),
+    }
 }
 
-fn element0(input: &mut &str) -> ParseResult<Box<[ast::Element]>> {
-    let mut elems = Vec::<ast::Element>::new();
+/// `INCLUDE` の後ろの部分を読み取る。
+fn include(input: &mut Input<'_>) -> ParseResult<ast::Include> {
+    let mut leading = trivia.parse_next(input)?;
+    let (path, path_span) = cut_err(
+        delimited('"', take_till(0.., ['"', '\n', '\r']), '"')
+            .context(StrContext::Label("include path"))
+            .with_span(),
+    )
+    .parse_next(input)?;
+    let path_span = span(input, path_span);
+    leading.extend(trivia.parse_next(input)?);
+    cut_err(expected(';')).parse_next(input)?;
+
+    Ok(ast::Include {
+        path: path.to_owned(),
+        path_span,
+        leading: without_blank_lines(leading),
+    })
+}
 
-    match opt(element).parse_next(input) {
-        Ok(Some(first)) => elems.push(first),
-        Ok(None) => return Ok(elems.into()),
-        Err(e) => return Err(e),
-    }
+/// `IF` の後ろの部分を読み取る。各分岐の中身は `item` で読み取る。
+fn if_rest<'a, T: HasLeading>(
+    input: &mut Input<'a>,
+    item: fn(&mut Input<'a>) -> ParseResult<T>,
+) -> ParseResult<ast::If<T>> {
+    let mut leading = trivia.parse_next(input)?;
+    let cond = cut_err(cond_or.context(StrContext::Label("condition"))).parse_next(input)?;
+    leading.extend(cut_err(terminated(trivia, expected('{'))).parse_next(input)?);
+    let then_branch = branch(input, item)?;
+
+    let else_branch =
+        if let Some(pre) = opt(terminated(trivia, keyword("else"))).parse_next(input)? {
+            leading.extend(pre);
+            leading.extend(cut_err(terminated(trivia, expected('{'))).parse_next(input)?);
+            Some(branch(input, item)?)
+        } else {
+            None
+        };
+
+    Ok(ast::If {
+        cond,
+        then_branch,
+        else_branch,
+        leading: without_blank_lines(leading),
+    })
+}
 
-    let remain: Vec<_> = repeat(0.., preceded(ign, element)).parse_next(input)?;
-    elems.extend(remain);
+/// IF の分岐の '{' より後ろの部分を読み取る。
+fn branch<'a, T: HasLeading>(
+    input: &mut Input<'a>,
+    item: fn(&mut Input<'a>) -> ParseResult<T>,
+) -> ParseResult<ast::Branch<T>> {
+    let (items, trailing) = item_list(input, item)?;
+    cut_err(expected('}')).parse_next(input)?;
+
+    Ok(ast::Branch {
+        items: items.into(),
+        trailing: trailing.into(),
+    })
+}
+
+fn cond_or(input: &mut Input<'_>) -> ParseResult<ast::Cond> {
+    let first = cond_and.parse_next(input)?;
+    let rest: Vec<_> =
+        repeat(0.., preceded((ws0, "||", ws0), cut_err(cond_and))).parse_next(input)?;
 
-    Ok(elems.into())
+    Ok(rest.into_iter().fold(first, |lhs, rhs| {
+        ast::Cond::Or(Box::new(lhs), Box::new(rhs))
+    }))
 }
 
-fn element(input: &mut &str) -> ParseResult<ast::Element> {
-    seq! { ast::Element {
-        name: identifier,
-        _: cut_err(delimited(ign, ':', ign)),
-        attrs: cut_err(attribute1),
-        _: cut_err(preceded(ign, ';')),
-    }}
+fn cond_and(input: &mut Input<'_>) -> ParseResult<ast::Cond> {
+    let first = cond_unary.parse_next(input)?;
+    let rest: Vec<_> =
+        repeat(0.., preceded((ws0, "&&", ws0), cut_err(cond_unary))).parse_next(input)?;
+
+    Ok(rest.into_iter().fold(first, |lhs, rhs| {
+        ast::Cond::And(Box::new(lhs), Box::new(rhs))
+    }))
+}
+
+fn cond_unary(input: &mut Input<'_>) -> ParseResult<ast::Cond> {
+    alt((
+        preceded(('!', ws0), cut_err(cond_unary)).map(|cond| ast::Cond::Not(Box::new(cond))),
+        delimited(('(', ws0), cut_err(cond_or), cut_err((ws0, expected(')')))),
+        preceded(
+            (keyword("defined"), ws0, '(', ws0),
+            cut_err(terminated(identifier, (ws0, expected(')')))),
+        )
+        .map(ast::Cond::Defined),
+    ))
     .parse_next(input)
 }
 
-fn attribute1(input: &mut &str) -> ParseResult<Box<[ast::Attribute]>> {
+/// ブロック名の後ろの部分を読み取る。
+fn block(input: &mut Input<'_>, name: String, name_span: ast::Span) -> ParseResult<ast::Block> {
+    let mut leading = cut_err(terminated(trivia, expected('{'))).parse_next(input)?;
+    let (mut items, mut trailing) = item_list(input, block_item)?;
+    cut_err(expected('}')).parse_next(input)?;
+
+    // ブロック名と '{' の間のコメントなどは最初の項目 (なければ末尾) に付ける。
+    if let Some(first) = items.first_mut() {
+        let first = first.leading_mut();
+        leading.extend(first.iter().cloned());
+        *first = leading.into();
+    } else {
+        leading.extend(trailing);
+        trailing = leading;
+    }
+
+    Ok(ast::Block {
+        name,
+        name_span,
+        items: items.into(),
+        leading: [].into(),
+        trailing: trailing.into(),
+    })
+}
+
+fn block_item(input: &mut Input<'_>) -> ParseResult<ast::BlockItem> {
+    // 要素名が "IF" の場合と区別するため、後ろに ':' が続かないことを確認する。
+    let is_if = opt(terminated(keyword("if"), peek(not((trivia, ':')))))
+        .parse_next(input)?
+        .is_some();
+
+    if is_if {
+        if_rest(input, block_item).map(ast::BlockItem::If)
+    } else {
+        element.map(ast::BlockItem::Element).parse_next(input)
+    }
+}
+
+fn element(input: &mut Input<'_>) -> ParseResult<ast::Element> {
+    let (name, name_span) = alt((identifier, quoted_name, main_outfile_name))
+        .with_span()
+        .parse_next(input)?;
+    let name_span = span(input, name_span);
+    let mut pending = cut_err(terminated(trivia, expected(':'))).parse_next(input)?;
+    pending.extend(trivia.parse_next(input)?);
+
+    let mut attrs = cut_err(attribute1).parse_next(input)?;
+    // ':' 前後のコメントは最初の属性に付ける。
+    if !pending.is_empty() {
+        let first = &mut attrs[0];
+        pending.extend(first.leading.iter().cloned());
+        first.leading = without_blank_lines(pending);
+    }
+
+    let trailing = trivia.parse_next(input)?;
+    cut_err(expected(';')).parse_next(input)?;
+    // ';' と同じ行にあるコメント。
+    let line_comment = opt(preceded(take_while(0.., [' ', '\t']), comment)).parse_next(input)?;
+
+    Ok(ast::Element {
+        name,
+        name_span,
+        attrs,
+        leading: [].into(),
+        trailing: without_blank_lines(trailing),
+        line_comment,
+    })
+}
+
+fn attribute1(input: &mut Input<'_>) -> ParseResult<Box<[ast::Attribute]>> {
     let mut attrs = vec![attribute.parse_next(input)?];
 
-    let remain: Vec<_> = repeat(0.., preceded(attributes_sep, attribute)).parse_next(input)?;
+    let remain: Vec<_> = repeat(
+        0..,
+        (attributes_sep, attribute).map(|(sep, mut attr)| {
+            // 区切りの前後のコメントは後ろの属性に付ける。
+            let mut leading = sep;
+            leading.extend(attr.leading.iter().cloned());
+            attr.leading = without_blank_lines(leading);
+            attr
+        }),
+    )
+    .parse_next(input)?;
     attrs.extend(remain);
 
     Ok(attrs.into())
 }
 
-fn attributes_sep(input: &mut &str) -> ParseResult<()> {
-    alt((delimited(ign, ',', ign).void(), ws1.void())).parse_next(input)
+fn attributes_sep(input: &mut Input<'_>) -> ParseResult<Vec<ast::Trivia>> {
+    alt((
+        (trivia, ',', trivia).map(|(mut pre, _, post)| {
+            pre.extend(post);
+            pre
+        }),
+        ws1.map(|_| Vec::new()),
+    ))
+    .parse_next(input)
 }
 
-fn attribute(input: &mut &str) -> ParseResult<ast::Attribute> {
-    seq! { ast::Attribute {
-        key: identifier.map(|s| s.to_ascii_lowercase()), // 小文字に統一
-        _: cut_err(attribute_kv_sep),
-        value: cut_err(attribute_value),
-    }}
-    .parse_next(input)
+fn attribute(input: &mut Input<'_>) -> ParseResult<ast::Attribute> {
+    let (key, key_span) = identifier
+        .map(|s| s.to_ascii_lowercase()) // 小文字に統一
+        .with_span()
+        .parse_next(input)?;
+    let leading = cut_err(attribute_kv_sep).parse_next(input)?;
+    let (value, value_span) = cut_err(
+        attribute_value
+            .context(StrContext::Label("attribute value"))
+            .with_span(),
+    )
+    .parse_next(input)?;
+
+    Ok(ast::Attribute {
+        key,
+        key_span: span(input, key_span),
+        value,
+        value_span: span(input, value_span),
+        leading: without_blank_lines(leading),
+    })
 }
 
-fn attribute_kv_sep(input: &mut &str) -> ParseResult<()> {
-    alt((delimited(ign, '=', ign).void(), ws1.void())).parse_next(input)
+fn attribute_kv_sep(input: &mut Input<'_>) -> ParseResult<Vec<ast::Trivia>> {
+    alt((
+        (trivia, '=', trivia).map(|(mut pre, _, post)| {
+            pre.extend(post);
+            pre
+        }),
+        ws1.map(|_| Vec::new()),
+    ))
+    .parse_next(input)
 }
 
-fn attribute_value(input: &mut &str) -> ParseResult<ast::Value> {
+fn attribute_value(input: &mut Input<'_>) -> ParseResult<ast::Value> {
     alt((
         boolean.map(ast::Value::Bool),
-        identifier.map(ast::Value::Ident),
+        identifier_or_alternatives,
         output_file.map(ast::Value::String),
         string.map(ast::Value::String),
+        "%S".value(ast::Value::StartAddr),
         uint.map(ast::Value::Uint),
     ))
     .parse_next(input)
 }
 
-fn boolean(input: &mut &str) -> ParseResult<bool> {
+/// 識別子、または '|' で区切られた識別子のリスト。
+fn identifier_or_alternatives(input: &mut Input<'_>) -> ParseResult<ast::Value> {
+    let first = identifier.parse_next(input)?;
+    let rest: Vec<_> = repeat(
+        0..,
+        preceded(
+            (
+                take_while(0.., [' ', '\t']),
+                '|',
+                take_while(0.., [' ', '\t']),
+            ),
+            cut_err(identifier),
+        ),
+    )
+    .parse_next(input)?;
+
+    if rest.is_empty() {
+        Ok(ast::Value::Ident(first))
+    } else {
+        let mut idents = vec![first];
+        idents.extend(rest);
+        Ok(ast::Value::Alternatives(idents.into()))
+    }
+}
+
+fn boolean(input: &mut Input<'_>) -> ParseResult<bool> {
     alt((
         Caseless("yes").value(true),
         Caseless("no").value(false),
@@ -118,7 +378,7 @@ fn boolean(input: &mut &str) -> ParseResult<bool> {
     .parse_next(input)
 }
 
-fn identifier(input: &mut &str) -> ParseResult<String> {
+fn identifier(input: &mut Input<'_>) -> ParseResult<String> {
     (
         one_of(|ch: char| ch.is_ascii_alphabetic() || ch == '_'),
         take_while(0.., |ch: char| ch.is_ascii_alphanumeric() || ch == '_'),
@@ -128,18 +388,30 @@ fn identifier(input: &mut &str) -> ParseResult<String> {
         .parse_next(input)
 }
 
-fn output_file(input: &mut &str) -> ParseResult<ast::FormatString> {
+/// 引用符で囲まれた要素名 (セグメント名のパターンなど)。
+fn quoted_name(input: &mut Input<'_>) -> ParseResult<String> {
+    delimited('"', take_till(1.., ['"', '\n']), '"')
+        .map(str::to_owned)
+        .parse_next(input)
+}
+
+/// 要素名としての "%O" (FILES ブロックでメイン出力ファイルを指す)。
+fn main_outfile_name(input: &mut Input<'_>) -> ParseResult<String> {
+    "%O".map(str::to_owned).parse_next(input)
+}
+
+fn output_file(input: &mut Input<'_>) -> ParseResult<ast::FormatString> {
     "%O".value(ast::FormatString {
         parts: [ast::FormatStringPart::MainOutFile].into(),
     })
     .parse_next(input)
 }
 
-fn string(input: &mut &str) -> ParseResult<ast::FormatString> {
+fn string(input: &mut Input<'_>) -> ParseResult<ast::FormatString> {
     delimited('"', string_inner, '"').parse_next(input)
 }
 
-fn string_inner(input: &mut &str) -> ParseResult<ast::FormatString> {
+fn string_inner(input: &mut Input<'_>) -> ParseResult<ast::FormatString> {
     let parts: Vec<_> = repeat(0.., string_part).parse_next(input)?;
 
     Ok(ast::FormatString {
@@ -147,7 +419,7 @@ fn string_inner(input: &mut &str) -> ParseResult<ast::FormatString> {
     })
 }
 
-fn string_part(input: &mut &str) -> ParseResult<ast::FormatStringPart> {
+fn string_part(input: &mut Input<'_>) -> ParseResult<ast::FormatStringPart> {
     alt((
         "%O".value(ast::FormatStringPart::MainOutFile),
         "%%".value(ast::FormatStringPart::EscapedPercent),
@@ -156,13 +428,13 @@ fn string_part(input: &mut &str) -> ParseResult<ast::FormatStringPart> {
     .parse_next(input)
 }
 
-fn string_part_literal(input: &mut &str) -> ParseResult<ast::FormatStringPart> {
+fn string_part_literal(input: &mut Input<'_>) -> ParseResult<ast::FormatStringPart> {
     take_till(1.., ['"', '%'])
         .map(|s: &str| ast::FormatStringPart::Literal(s.to_owned()))
         .parse_next(input)
 }
 
-fn uint(input: &mut &str) -> ParseResult<u32> {
+fn uint(input: &mut Input<'_>) -> ParseResult<u32> {
     dispatch! { peek(any);
         '%' => preceded('%', uint_bin_digits),
         '$' => preceded('$', uint_hex_digits),
@@ -172,31 +444,82 @@ fn uint(input: &mut &str) -> ParseResult<u32> {
     .parse_next(input)
 }
 
-fn uint_bin_digits(input: &mut &str) -> ParseResult<u32> {
+fn uint_bin_digits(input: &mut Input<'_>) -> ParseResult<u32> {
     take_while(1.., '0'..='1')
         .try_map(|s| u32::from_str_radix(s, 2))
         .parse_next(input)
 }
 
-fn uint_dec_digits(input: &mut &str) -> ParseResult<u32> {
+fn uint_dec_digits(input: &mut Input<'_>) -> ParseResult<u32> {
     take_while(1.., '0'..='9')
         .try_map(str::parse)
         .parse_next(input)
 }
 
-fn uint_hex_digits(input: &mut &str) -> ParseResult<u32> {
+fn uint_hex_digits(input: &mut Input<'_>) -> ParseResult<u32> {
     take_while(1.., ('0'..='9', 'A'..='F', 'a'..='f'))
         .try_map(|s| u32::from_str_radix(s, 16))
         .parse_next(input)
 }
 
-/// コメントと空白文字を読み飛ばす。
-fn ign(input: &mut &str) -> ParseResult<()> {
-    repeat(0.., alt((comment, ws1.void())))
-        .map(|()| ())
+/// コメントと空白文字を読み取る。
+///
+/// 空白文字のうち、2 つ以上の改行を含むもの (空行) は `Trivia::BlankLine` とする。
+fn trivia(input: &mut Input<'_>) -> ParseResult<Vec<ast::Trivia>> {
+    repeat(
+        0..,
+        alt((
+            comment.map(|s| Some(ast::Trivia::Comment(s))),
+            ws1.map(|ws: &str| (ws.matches('\n').count() >= 2).then_some(ast::Trivia::BlankLine)),
+        )),
+    )
+    .map(|trivias: Vec<_>| trivias.into_iter().flatten().collect())
+    .parse_next(input)
+}
+
+/// '#' から行末までのコメントを読み取り、'#' を除いた内容を返す。
+fn comment(input: &mut Input<'_>) -> ParseResult<String> {
+    preceded('#', take_till(0.., ['\n', '\r']))
+        .map(|s: &str| s.trim_end().to_owned())
         .parse_next(input)
 }
 
-fn comment(input: &mut &str) -> ParseResult<()> {
-    ('#', take_till(1.., ['\n', '\r'])).void().parse_next(input)
+/// 属性に付けるコメントから空行を除く (属性間の空行は保持しない)。
+fn without_blank_lines(trivias: Vec<ast::Trivia>) -> Box<[ast::Trivia]> {
+    trivias
+        .into_iter()
+        .filter(|trivia| matches!(trivia, ast::Trivia::Comment(_)))
+        .collect()
+}
+
+/// キーワード (大文字小文字を区別しない識別子) を読み取る。
+fn keyword<'a>(
+    word: &'static str,
+) -> impl winnow::Parser<Input<'a>, String, ErrMode<ContextError>> {
+    identifier.verify(move |s: &String| s.eq_ignore_ascii_case(word))
+}
+
+/// 入力内の範囲を、全体を通した位置に変換する。
+fn span(input: &Input<'_>, range: std::ops::Range<usize>) -> ast::Span {
+    ast::Span::from(input.state + range.start..input.state + range.end)
+}
+
+/// 1 文字を読み取る。失敗した場合、エラーにはその文字を期待していた旨が含まれる。
+fn expected<'a>(ch: char) -> impl winnow::Parser<Input<'a>, char, ErrMode<ContextError>> {
+    ch.context(StrContext::Expected(StrContextValue::CharLiteral(ch)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_comment_line() {
+        let script = parse(
+            "#\nMEMORY {\n    #\n    ROM: start = $8000, size = $100; #\n}\n#",
+            0,
+        )
+        .unwrap();
+        assert_eq!(script.items.len(), 1);
+    }
 }
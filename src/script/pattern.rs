@@ -0,0 +1,198 @@
+//! セグメント名、オブジェクトファイル名のパターン (glob) の処理。
+
+use std::collections::BTreeSet;
+
+use super::{LinkScript, LinkScriptIssue, LinkScriptSegment};
+
+impl LinkScript {
+    /// セグメント名のパターンを、オブジェクトファイル内で使われているセグメント名 `seg_names`
+    /// に展開したリンカスクリプトを返す。既定ルールが適用された名前については警告を返す。
+    ///
+    /// 各名前は以下の優先順位でリンカスクリプト内のセグメントに対応付けられる:
+    ///
+    /// 1. 名前が完全に一致するセグメント。
+    /// 2. 名前にマッチするパターン (`*`, `?` を含む名前) のうち、リンカスクリプト内で最初のもの。
+    /// 3. 既定ルール (名前が `*` のみのもの)。リンカスクリプト内の位置によらず最後に試される。
+    ///
+    /// パターンは、マッチした名前ごとの (属性が同じ) セグメントに置き換えられる (名前順)。
+    /// どれにも対応しない名前は無視される。
+    pub fn expand_segment_patterns<'a, I>(&self, seg_names: I) -> (Self, Box<[LinkScriptIssue]>)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let seg_names: BTreeSet<&str> = seg_names.into_iter().collect();
+
+        let default_i = self
+            .segs
+            .iter()
+            .position(LinkScriptSegment::is_default_rule);
+
+        // 各パターンにマッチした名前。
+        let mut matched = vec![Vec::<&str>::new(); self.segs.len()];
+        let mut issues = Vec::<LinkScriptIssue>::new();
+
+        for name in seg_names {
+            if self.segs.iter().any(|seg| seg.name == name) {
+                continue;
+            }
+
+            let pattern_i = self
+                .segs
+                .iter()
+                .position(|seg| {
+                    seg.is_pattern() && !seg.is_default_rule() && glob_match(&seg.name, name)
+                })
+                .or(default_i);
+            let Some(pattern_i) = pattern_i else {
+                continue;
+            };
+
+            if Some(pattern_i) == default_i {
+                let seg = &self.segs[pattern_i];
+                issues.push(LinkScriptIssue::warning(format!(
+                    "segment '{name}' is not listed in the linker script, placed into memory '{}' by default rule",
+                    self.mems[seg.mem_i.get()].name
+                )));
+            }
+            matched[pattern_i].push(name);
+        }
+
+        let segs: Box<[_]> = self
+            .segs
+            .iter()
+            .zip(matched)
+            .flat_map(|(seg, names)| {
+                if seg.is_pattern() {
+                    names
+                        .into_iter()
+                        .map(|name| LinkScriptSegment {
+                            name: name.to_owned(),
+                            ..seg.clone()
+                        })
+                        .collect()
+                } else {
+                    vec![seg.clone()]
+                }
+            })
+            .collect();
+
+        let script = Self {
+            outfiles: self.outfiles.clone(),
+            outfile_sizes: self.outfile_sizes.clone(),
+            mems: self.mems.clone(),
+            segs,
+            syms: self.syms.clone(),
+        };
+
+        (script, issues.into())
+    }
+}
+
+impl LinkScriptSegment {
+    /// order 属性に従い、オブジェクトファイル `obj_name` のセクションを置くグループの番号を返す
+    /// (小さいほど前)。
+    ///
+    /// 最初にマッチしたパターンのグループとなる。ただし、`*` のみのパターンは他のどれにもマッチしない
+    /// オブジェクトファイルのグループを表す (なければ末尾のグループ)。
+    pub fn order_rank(&self, obj_name: &str) -> usize {
+        let rest = self
+            .order
+            .iter()
+            .position(|pattern| pattern == "*")
+            .unwrap_or(self.order.len());
+
+        self.order
+            .iter()
+            .position(|pattern| pattern != "*" && object_name_matches(pattern, obj_name))
+            .unwrap_or(rest)
+    }
+}
+
+/// オブジェクトファイル名がパターンにマッチするかどうかを返す。
+///
+/// ディレクトリを除いたファイル名、ライブラリ内のモジュール名 (`lib(module)` の `module`) も対象とする。
+fn object_name_matches(pattern: &str, obj_name: &str) -> bool {
+    let file_name = obj_name.rsplit(['/', '\\']).next().unwrap_or(obj_name);
+    let module_name = obj_name
+        .strip_suffix(')')
+        .and_then(|s| s.rsplit_once('('))
+        .map(|(_, module_name)| module_name);
+
+    glob_match(pattern, obj_name)
+        || glob_match(pattern, file_name)
+        || module_name.is_some_and(|module_name| glob_match(pattern, module_name))
+}
+
+/// glob パターン (`*` は任意の文字列、`?` は任意の 1 文字) に名前がマッチするかどうかを返す。
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // 最後に現れた '*' の位置と、それに対応させた名前の位置 (バックトラック用)。
+    let mut star = None::<(usize, usize)>;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&ch) if ch == '?' || ch == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => {
+                // '*' に対応させる文字を 1 つ増やしてやり直す。
+                let Some((star_p, star_n)) = star else {
+                    return false;
+                };
+                star = Some((star_p, star_n + 1));
+                p = star_p + 1;
+                n = star_n + 1;
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("CODE", "CODE"));
+        assert!(!glob_match("CODE", "CODE2"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "RODATA"));
+        assert!(glob_match("BANK?", "BANK1"));
+        assert!(!glob_match("BANK?", "BANK"));
+        assert!(!glob_match("BANK?", "BANK10"));
+        assert!(glob_match("BANK*", "BANK10"));
+        assert!(glob_match("*DATA", "RODATA"));
+        assert!(!glob_match("*DATA", "DATA_1"));
+        assert!(glob_match("*_*_*", "A_B_C"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYcZ"));
+    }
+
+    #[test]
+    fn object_name_matches_file_and_module_name() {
+        assert!(object_name_matches("crt0.o", "lib/crt0.o"));
+        assert!(object_name_matches("*.o", "obj/main.o"));
+        assert!(object_name_matches("crt0.o", "nes.lib(crt0.o)"));
+        assert!(!object_name_matches("crt0.o", "main.o"));
+    }
+
+    #[test]
+    fn pattern_segment_cannot_have_start() {
+        let script = "MEMORY { ROM: start = $8000, size = $100; }\nSEGMENTS { \"BANK*\": load = ROM, start = $8000; }";
+        let e = LinkScript::load(script, "out.bin").unwrap_err();
+        assert!(
+            format!("{e:#}").contains("cannot be used with segment name pattern"),
+            "{e:#}"
+        );
+    }
+}
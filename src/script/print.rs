@@ -0,0 +1,336 @@
+//! リンカスクリプトの AST を整形して出力する。
+//!
+//! * インデントは 4 スペース (IF の中は 1 段深くする)。ブロック間には空行を 1 つ入れる (連続する INCLUDE の間は除く)。
+//! * ブロック内の要素名は揃えて出力する (IF で区切られた範囲ごと)。
+//! * IF の条件式の括弧は必要なもののみ残す。
+//! * 属性は既知のものを一定の順序に並べ替える (未知のものは元の順序のまま後ろに置く)。
+//! * 整数は '$' 付きの 16 進数で出力する。
+//! * コメントは全て保持する。空行は連続するものを 1 つにまとめる。
+//!
+//! 出力を再度パースして整形したものは元の出力と一致する。
+
+use std::fmt::Write as _;
+
+use super::ast;
+
+const INDENT: &str = "    ";
+
+/// AST を整形した文字列を返す。
+pub fn print(script: &ast::Script) -> String {
+    let mut out = String::new();
+
+    print_items(&mut out, &script.items, "");
+
+    if has_comment(&script.trailing) {
+        if !script.items.is_empty() {
+            out.push('\n');
+        }
+        print_trivias(
+            &mut out,
+            trim_trailing_blank_lines(&script.trailing),
+            "",
+            true,
+        );
+    }
+
+    out
+}
+
+/// トップレベル (または IF 内) の項目の並びを出力する。
+fn print_items(out: &mut String, items: &[ast::Item], indent: &str) {
+    let mut prev_include = false;
+
+    for (i, item) in items.iter().enumerate() {
+        let is_include = matches!(item, ast::Item::Include(_));
+        // 項目間には空行を入れる。ただし、連続する INCLUDE の間には入れない (元の空行は保持する)。
+        let consecutive = is_include && prev_include;
+        if i > 0 && !consecutive {
+            out.push('\n');
+        }
+
+        match item {
+            ast::Item::Block(block) => {
+                print_trivias(out, &block.leading, indent, true);
+                print_block(out, block, indent);
+            }
+            ast::Item::Include(include) => {
+                print_trivias(out, &include.leading, indent, !consecutive);
+                writeln!(out, "{indent}INCLUDE \"{}\";", include.path).unwrap();
+            }
+            ast::Item::If(if_) => {
+                print_trivias(out, &if_.leading, indent, true);
+                print_if(out, if_, indent, &|out, branch, indent| {
+                    print_items(out, &branch.items, indent);
+                    print_trivias(
+                        out,
+                        trim_trailing_blank_lines(&branch.trailing),
+                        indent,
+                        branch.items.is_empty(),
+                    );
+                });
+            }
+        }
+
+        prev_include = is_include;
+    }
+}
+
+/// IF を出力する (直前のコメントなどを除く)。各分岐の中身は `print_branch` で出力する。
+fn print_if<T>(
+    out: &mut String,
+    if_: &ast::If<T>,
+    indent: &str,
+    print_branch: &dyn Fn(&mut String, &ast::Branch<T>, &str),
+) {
+    let inner = format!("{indent}{INDENT}");
+
+    writeln!(out, "{indent}IF {} {{", format_cond(&if_.cond, 0)).unwrap();
+    print_branch(out, &if_.then_branch, &inner);
+    if let Some(branch) = &if_.else_branch {
+        writeln!(out, "{indent}}} ELSE {{").unwrap();
+        print_branch(out, branch, &inner);
+    }
+    writeln!(out, "{indent}}}").unwrap();
+}
+
+/// 条件式を文字列にする。
+///
+/// `prec` は外側の演算子の優先順位 (0: なし, 1: `||`, 2: `&&`, 3: `!`)。必要な場合のみ括弧を付ける。
+fn format_cond(cond: &ast::Cond, prec: u8) -> String {
+    let (s, own_prec) = match cond {
+        ast::Cond::Defined(name) => (format!("defined({name})"), 4),
+        ast::Cond::Not(cond) => (format!("!{}", format_cond(cond, 3)), 3),
+        // 左結合なので、右辺にのみ同じ演算子が来たときに括弧を付ける。
+        ast::Cond::And(lhs, rhs) => (
+            format!("{} && {}", format_cond(lhs, 2), format_cond(rhs, 3)),
+            2,
+        ),
+        ast::Cond::Or(lhs, rhs) => (
+            format!("{} || {}", format_cond(lhs, 1), format_cond(rhs, 2)),
+            1,
+        ),
+    };
+
+    if own_prec < prec {
+        format!("({s})")
+    } else {
+        s
+    }
+}
+
+fn print_block(out: &mut String, block: &ast::Block, indent: &str) {
+    let inner = format!("{indent}{INDENT}");
+
+    writeln!(out, "{indent}{} {{", block.name.to_ascii_uppercase()).unwrap();
+    print_block_items(out, &block.items, &block.name, &inner);
+    print_trivias(
+        out,
+        trim_trailing_blank_lines(&block.trailing),
+        &inner,
+        block.items.is_empty(),
+    );
+    writeln!(out, "{indent}}}").unwrap();
+}
+
+/// ブロック内 (または、その中の IF 内) の項目の並びを出力する。
+fn print_block_items(out: &mut String, items: &[ast::BlockItem], block_name: &str, indent: &str) {
+    // 要素名 (':' 含む) の幅を揃える。
+    let width = items
+        .iter()
+        .filter_map(|item| match item {
+            ast::BlockItem::Element(elem) => Some(format_name(&elem.name).len() + 1),
+            ast::BlockItem::If(_) => None,
+        })
+        .max()
+        .unwrap_or(0);
+
+    for (i, item) in items.iter().enumerate() {
+        match item {
+            ast::BlockItem::Element(elem) => {
+                print_trivias(out, &elem.leading, indent, i == 0);
+                print_element(out, elem, block_name, width, indent);
+            }
+            ast::BlockItem::If(if_) => {
+                print_trivias(out, &if_.leading, indent, i == 0);
+                print_if(out, if_, indent, &|out, branch, indent| {
+                    print_block_items(out, &branch.items, block_name, indent);
+                    print_trivias(
+                        out,
+                        trim_trailing_blank_lines(&branch.trailing),
+                        indent,
+                        branch.items.is_empty(),
+                    );
+                });
+            }
+        }
+    }
+}
+
+fn print_element(
+    out: &mut String,
+    elem: &ast::Element,
+    block_name: &str,
+    width: usize,
+    indent: &str,
+) {
+    let attrs = sorted_attrs(&elem.attrs, block_name);
+    let label = format!("{}:", format_name(&elem.name));
+
+    let multiline = !elem.trailing.is_empty() || attrs.iter().any(|attr| !attr.leading.is_empty());
+
+    if multiline {
+        // 要素内にコメントがある場合は 1 属性 1 行とする。
+        let attr_indent = format!("{indent}{INDENT}");
+        writeln!(out, "{indent}{label}").unwrap();
+        for (i, attr) in attrs.iter().enumerate() {
+            print_trivias(out, &attr.leading, &attr_indent, true);
+            let sep = if i + 1 < attrs.len() { "," } else { "" };
+            writeln!(out, "{attr_indent}{}{sep}", format_attr(attr)).unwrap();
+        }
+        print_trivias(out, &elem.trailing, &attr_indent, true);
+        write!(out, "{indent};").unwrap();
+    } else {
+        let attrs: Vec<_> = attrs.iter().map(|attr| format_attr(attr)).collect();
+        write!(out, "{indent}{label:<width$} {};", attrs.join(", ")).unwrap();
+    }
+
+    if let Some(comment) = &elem.line_comment {
+        write!(out, " #{comment}").unwrap();
+    }
+    out.push('\n');
+}
+
+/// コメントと空行を出力する。
+///
+/// 連続する空行は 1 つにまとめる。`at_start` が真なら先頭の空行は出力しない。
+fn print_trivias(out: &mut String, trivias: &[ast::Trivia], indent: &str, at_start: bool) {
+    let mut blank = false;
+    let mut at_start = at_start;
+
+    for trivia in trivias {
+        match trivia {
+            ast::Trivia::BlankLine => blank = true,
+            ast::Trivia::Comment(comment) => {
+                if blank && !at_start {
+                    out.push('\n');
+                }
+                writeln!(out, "{indent}#{comment}").unwrap();
+                blank = false;
+                at_start = false;
+            }
+        }
+    }
+
+    // コメントの後に空行があった場合、後続の要素との間に空行を残す。
+    if blank && !at_start {
+        out.push('\n');
+    }
+}
+
+/// 末尾の空行を除く (ブロックやファイルの末尾には空行を置かない)。
+fn trim_trailing_blank_lines(trivias: &[ast::Trivia]) -> &[ast::Trivia] {
+    let len = trivias
+        .iter()
+        .rposition(|trivia| matches!(trivia, ast::Trivia::Comment(_)))
+        .map_or(0, |i| i + 1);
+
+    &trivias[..len]
+}
+
+fn has_comment(trivias: &[ast::Trivia]) -> bool {
+    trivias
+        .iter()
+        .any(|trivia| matches!(trivia, ast::Trivia::Comment(_)))
+}
+
+/// 属性を所定の順序 (`ast::*_ATTR_KEYS`) に並べ替える。
+fn sorted_attrs<'a>(attrs: &'a [ast::Attribute], block_name: &str) -> Vec<&'a ast::Attribute> {
+    let order = match block_name {
+        "files" => ast::FILES_ATTR_KEYS,
+        "memory" => ast::MEMORY_ATTR_KEYS,
+        "segments" => ast::SEGMENTS_ATTR_KEYS,
+        "symbols" => ast::SYMBOLS_ATTR_KEYS,
+        _ => &[],
+    };
+
+    let mut attrs: Vec<_> = attrs.iter().collect();
+    attrs.sort_by_key(|attr| {
+        order
+            .iter()
+            .position(|&key| key == attr.key)
+            .unwrap_or(order.len())
+    });
+
+    attrs
+}
+
+/// 要素名を出力形式にする。識別子 (または "%O") でなければ引用符で囲む。
+fn format_name(name: &str) -> String {
+    if ast::is_identifier(name) || name == "%O" {
+        name.to_owned()
+    } else {
+        format!("\"{name}\"")
+    }
+}
+
+fn format_attr(attr: &ast::Attribute) -> String {
+    format!("{} = {}", attr.key, format_value(&attr.key, &attr.value))
+}
+
+fn format_value(key: &str, value: &ast::Value) -> String {
+    match value {
+        ast::Value::Uint(x) => {
+            // バイト値をとる属性は 2 桁、それ以外は最低 4 桁とする。
+            if matches!(key, "fillval" | "bank") {
+                format!("${x:02X}")
+            } else {
+                format!("${x:04X}")
+            }
+        }
+        ast::Value::Bool(b) => (if *b { "yes" } else { "no" }).to_owned(),
+        ast::Value::String(s) => format_string(s),
+        ast::Value::Ident(ident) => ident.clone(),
+        ast::Value::Alternatives(idents) => idents.join("|"),
+        ast::Value::StartAddr => "%S".to_owned(),
+    }
+}
+
+fn format_string(s: &ast::FormatString) -> String {
+    // `file = %O` のように引用符なしで書かれることが多いので、それに合わせる。
+    if let [ast::FormatStringPart::MainOutFile] = &*s.parts {
+        return "%O".to_owned();
+    }
+
+    let mut res = String::from('"');
+    for part in &s.parts {
+        match part {
+            ast::FormatStringPart::Literal(lit) => res.push_str(lit),
+            ast::FormatStringPart::MainOutFile => res.push_str("%O"),
+            ast::FormatStringPart::EscapedPercent => res.push_str("%%"),
+        }
+    }
+    res.push('"');
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script::{builtin, builtin_targets, format, LinkScript};
+
+    #[test]
+    fn format_builtins_is_idempotent() {
+        for target in builtin_targets() {
+            let name = format!("{target}.cfg");
+            let script = builtin(target).unwrap();
+            let once = format(&name, script).unwrap();
+            let twice = format(&name, &once).unwrap();
+            assert_eq!(once, twice, "{name}");
+
+            // 整形しても評価結果は変わらない。
+            let orig = LinkScript::load(script, "out.bin").unwrap();
+            let formatted = LinkScript::load(&once, "out.bin").unwrap();
+            assert_eq!(format!("{orig:?}"), format!("{formatted:?}"), "{name}");
+        }
+    }
+}
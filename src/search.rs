@@ -0,0 +1,70 @@
+//! 入力ファイル (リンカスクリプト、オブジェクトファイル、ライブラリ) の検索パス。
+
+use std::path::{Path, PathBuf};
+
+/// ファイルの検索パス。
+///
+/// 指定されたパスがそのまま存在すればそれを使い、なければ登録されたディレクトリを順に探す。
+#[derive(Clone, Debug, Default)]
+pub struct SearchPath {
+    dirs: Vec<PathBuf>,
+}
+
+impl SearchPath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 検索ディレクトリを末尾に追加する。
+    pub fn push<P: Into<PathBuf>>(&mut self, dir: P) -> &mut Self {
+        self.dirs.push(dir.into());
+        self
+    }
+
+    /// 環境変数で指定されたディレクトリを末尾に追加する。
+    ///
+    /// 値はプラットフォームのパス区切り文字 (Unix なら ':') で区切って複数指定できる。
+    /// 環境変数が設定されていなければ何もしない。
+    pub fn push_env(&mut self, var: &str) -> &mut Self {
+        if let Some(value) = std::env::var_os(var) {
+            self.dirs
+                .extend(std::env::split_paths(&value).filter(|dir| !dir.as_os_str().is_empty()));
+        }
+        self
+    }
+
+    /// 環境変数で指定されたディレクトリのサブディレクトリを末尾に追加する (`$CC65_HOME/cfg` など)。
+    ///
+    /// 環境変数が設定されていなければ何もしない。
+    pub fn push_env_subdir(&mut self, var: &str, subdir: &str) -> &mut Self {
+        if let Some(value) = std::env::var_os(var).filter(|value| !value.is_empty()) {
+            self.dirs.push(Path::new(&value).join(subdir));
+        }
+        self
+    }
+
+    pub fn iter_dirs(
+        &self,
+    ) -> impl ExactSizeIterator<Item = &Path> + std::iter::FusedIterator + Clone {
+        self.dirs.iter().map(PathBuf::as_path)
+    }
+
+    /// ファイルを探し、見つかったパスを返す。
+    pub fn find<P: AsRef<Path>>(&self, name: P) -> Option<PathBuf> {
+        let name = name.as_ref();
+
+        if name.is_file() {
+            return Some(name.to_owned());
+        }
+
+        // 絶対パスの場合は検索ディレクトリを使わない。
+        if name.is_absolute() {
+            return None;
+        }
+
+        self.dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+}
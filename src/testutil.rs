@@ -0,0 +1,266 @@
+//! テスト用の xo65 オブジェクトファイルの生成。
+//!
+//! ca65 が出力する形式のうち、リンカのテストに必要な最小限のものだけを生成する。
+
+use xo65::Xo65;
+
+use crate::object::Object;
+
+/// テスト用の式。
+#[derive(Clone, Debug)]
+pub enum TestExpr {
+    Literal(i32),
+    /// インポートシンボル (インポートテーブル内インデックス)。
+    Symbol(usize),
+    /// セクション先頭からのオフセット (セクションテーブル内インデックス, オフセット)。
+    Section(usize, i32),
+    /// バンク番号 (`.bank(expr)`)。
+    Bank(Box<TestExpr>),
+    /// 下位バイト (`<expr`)。
+    Byte0(Box<TestExpr>),
+    /// 上位バイト (`>expr`)。
+    Byte1(Box<TestExpr>),
+}
+
+impl TestExpr {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Literal(value) => {
+                out.push(0x81);
+                out.extend(value.to_le_bytes());
+            }
+            Self::Symbol(imp_i) => {
+                out.push(0x82);
+                uleb(out, *imp_i);
+            }
+            Self::Section(sect_i, 0) => {
+                out.push(0x83);
+                uleb(out, *sect_i);
+            }
+            Self::Section(sect_i, off) => {
+                out.push(0x01);
+                Self::Section(*sect_i, 0).encode(out);
+                Self::Literal(*off).encode(out);
+            }
+            Self::Bank(expr) => Self::encode_unary(out, 0x45, expr),
+            Self::Byte0(expr) => Self::encode_unary(out, 0x48, expr),
+            Self::Byte1(expr) => Self::encode_unary(out, 0x49, expr),
+        }
+    }
+
+    fn encode_unary(out: &mut Vec<u8>, op: u8, expr: &TestExpr) {
+        out.push(op);
+        expr.encode(out);
+        out.push(0x00);
+    }
+}
+
+/// テスト用のオブジェクトファイルのビルダー。
+#[derive(Debug)]
+pub struct TestObject {
+    name: String,
+    strings: Vec<String>,
+    sects: Vec<TestSection>,
+    imports: Vec<(String, u8)>,
+    exports: Vec<(String, TestExpr, u8)>,
+}
+
+impl TestObject {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            strings: Vec::new(),
+            sects: Vec::new(),
+            imports: Vec::new(),
+            exports: Vec::new(),
+        }
+    }
+
+    /// セクションを追加し、そのインデックスを返す。
+    pub fn section<S: Into<String>>(&mut self, seg_name: S) -> usize {
+        self.sects.push(TestSection {
+            seg_name: seg_name.into(),
+            len: 0,
+            frags: Vec::new(),
+        });
+        self.sects.len() - 1
+    }
+
+    /// セクションにリテラルデータを追加する。
+    pub fn literal(&mut self, sect_i: usize, data: &[u8]) -> &mut Self {
+        let sect = &mut self.sects[sect_i];
+        let mut frag = vec![0x00];
+        uleb(&mut frag, data.len());
+        frag.extend(data);
+        sect.frags.push(frag);
+        sect.len += data.len();
+        self
+    }
+
+    /// セクションに初期値のない領域 (`.res`) を追加する。
+    pub fn fill(&mut self, sect_i: usize, len: usize) -> &mut Self {
+        let sect = &mut self.sects[sect_i];
+        let mut frag = vec![0x20];
+        uleb(&mut frag, len);
+        sect.frags.push(frag);
+        sect.len += len;
+        self
+    }
+
+    /// セクションに式を追加する (`len` は 1, 2, 3 のいずれか。符号なし)。
+    pub fn expr(&mut self, sect_i: usize, len: usize, expr: TestExpr) -> &mut Self {
+        let sect = &mut self.sects[sect_i];
+        let mut frag = vec![0x08 | len as u8];
+        expr.encode(&mut frag);
+        sect.frags.push(frag);
+        sect.len += len;
+        self
+    }
+
+    /// インポートシンボルを追加し、そのインデックスを返す。
+    pub fn import<S: Into<String>>(&mut self, name: S, addr_size: u8) -> usize {
+        self.imports.push((name.into(), addr_size));
+        self.imports.len() - 1
+    }
+
+    /// エクスポートシンボルを追加する。
+    pub fn export<S: Into<String>>(&mut self, name: S, expr: TestExpr, addr_size: u8) -> &mut Self {
+        self.exports.push((name.into(), expr, addr_size));
+        self
+    }
+
+    /// xo65 形式にエンコードする。
+    pub fn encode(mut self) -> Vec<u8> {
+        let src = self.string(&format!("{}.s", self.name));
+
+        let options = vec![0];
+
+        let mut files = vec![1];
+        uleb(&mut files, src);
+        files.extend(0u32.to_le_bytes());
+        uleb(&mut files, 0);
+
+        let mut sects = Vec::new();
+        uleb(&mut sects, self.sects.len());
+        for sect in std::mem::take(&mut self.sects) {
+            let mut body = Vec::new();
+            uleb(&mut body, self.string(&sect.seg_name));
+            uleb(&mut body, 0); // flags
+            uleb(&mut body, sect.len);
+            uleb(&mut body, 1); // align
+            body.push(2); // addr size
+            uleb(&mut body, sect.frags.len());
+            for frag in sect.frags {
+                body.extend(frag);
+                uleb(&mut body, 0); // line info
+            }
+            sects.extend((body.len() as u32).to_le_bytes());
+            sects.extend(body);
+        }
+
+        let mut imports = Vec::new();
+        uleb(&mut imports, self.imports.len());
+        for (name, addr_size) in std::mem::take(&mut self.imports) {
+            imports.push(addr_size);
+            uleb(&mut imports, self.string(&name));
+            uleb(&mut imports, 0);
+            uleb(&mut imports, 0);
+        }
+
+        let mut exports = Vec::new();
+        uleb(&mut exports, self.exports.len());
+        for (name, expr, addr_size) in std::mem::take(&mut self.exports) {
+            let name = self.string(&name);
+            match expr {
+                TestExpr::Literal(value) => {
+                    uleb(&mut exports, 0);
+                    exports.push(addr_size);
+                    uleb(&mut exports, name);
+                    exports.extend(value.to_le_bytes());
+                }
+                expr => {
+                    // 式で定義されたラベル。
+                    uleb(&mut exports, 0x30);
+                    exports.push(addr_size);
+                    uleb(&mut exports, name);
+                    expr.encode(&mut exports);
+                }
+            }
+            uleb(&mut exports, 0);
+            uleb(&mut exports, 0);
+        }
+
+        let dbg_syms = vec![0, 0];
+        let line_infos = vec![0];
+
+        let mut strings = Vec::new();
+        uleb(&mut strings, self.strings.len());
+        for s in &self.strings {
+            uleb(&mut strings, s.len());
+            strings.extend(s.as_bytes());
+        }
+
+        let tables = [
+            options,
+            files,
+            sects,
+            imports,
+            exports,
+            dbg_syms,
+            line_infos,
+            strings,
+            vec![0], // assertions
+            vec![0], // scopes
+            vec![0], // spans
+        ];
+
+        let mut header = Vec::from(*b"Uzna");
+        header.extend(0x11u16.to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        let mut body = Vec::new();
+        let mut off = 8 + 8 * tables.len();
+        for table in tables {
+            header.extend((off as u32).to_le_bytes());
+            header.extend((table.len() as u32).to_le_bytes());
+            off += table.len();
+            body.extend(table);
+        }
+
+        header.extend(body);
+        header
+    }
+
+    fn string(&mut self, s: &str) -> usize {
+        match self.strings.iter().position(|x| x == s) {
+            Some(i) => i,
+            None => {
+                self.strings.push(s.to_owned());
+                self.strings.len() - 1
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TestSection {
+    seg_name: String,
+    len: usize,
+    frags: Vec<Vec<u8>>,
+}
+
+/// エンコード済みのオブジェクトファイルを読み込む。
+pub fn parse_object<'data>(name: &str, bytes: &'data [u8]) -> Object<'data> {
+    Object::new(format!("{name}.o"), Xo65::parse(bytes).unwrap())
+}
+
+fn uleb(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let b = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(b);
+            break;
+        }
+        out.push(b | 0x80);
+    }
+}